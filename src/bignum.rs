@@ -0,0 +1,579 @@
+//============================================================================
+//
+// A simple Mandelbrot image generator in Rust
+//
+// Arbitrary precision fixed-point numbers for deep zoom reference orbits
+//
+// Copyright (c) 2014 Gavin Baker <gavinb@antonym.org>
+// Published under the MIT license
+//
+//============================================================================
+
+#![allow(dead_code)]
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Sub, Mul, Neg};
+use std::vec::Vec;
+
+//----------------------------------------------------------------------------
+
+// A signed fixed-point number with `frac` 32-bit limbs after the binary
+// point.  The magnitude is stored little-endian, so the value is
+// mag / 2^(32*frac).  The integer part grows as needed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BigFixed {
+    negative: bool,
+    mag: Vec<u32>,
+    frac: usize,
+}
+
+// Number of fractional limbs needed to hold `bits` bits after the point
+pub fn limbs_for_bits(bits: u32) -> usize {
+    bits.div_ceil(32) as usize
+}
+
+impl BigFixed {
+
+    pub fn zero(frac: usize) -> BigFixed {
+        BigFixed { negative: false, mag: vec![0; frac + 1], frac: frac }
+    }
+
+    pub fn precision(&self) -> usize {
+        self.frac
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    // Copy with a different number of fractional limbs, truncating if shorter
+    pub fn with_precision(&self, frac: usize) -> BigFixed {
+        let mag = if frac >= self.frac {
+            let mut m = vec![0; frac - self.frac];
+            m.extend_from_slice(&self.mag);
+            m
+        } else {
+            self.mag[self.frac - frac..].to_vec()
+        };
+        BigFixed::from_parts(self.negative, mag, frac)
+    }
+
+    // Exact conversion, since every finite f64 is a dyadic rational
+    pub fn from_f64(x: f64, frac: usize) -> BigFixed {
+        if !x.is_finite() || x == 0.0 {
+            return BigFixed::zero(frac);
+        }
+
+        let bits = x.to_bits();
+        let negative = (bits >> 63) == 1;
+        let exponent = ((bits >> 52) & 0x7ff) as i64;
+        let fraction = bits & ((1u64 << 52) - 1);
+        let (mantissa, exp2) = if exponent == 0 {
+            (fraction, -1074)
+        } else {
+            (fraction | (1u64 << 52), exponent - 1075)
+        };
+
+        let mut mag = vec![mantissa as u32, (mantissa >> 32) as u32];
+        let shift = exp2 + 32 * frac as i64;
+        if shift >= 0 {
+            shl_bits(&mut mag, shift as usize);
+        } else {
+            shr_bits(&mut mag, (-shift) as usize);
+        }
+
+        BigFixed::from_parts(negative, mag, frac)
+    }
+
+    // Parse a decimal string such as "-0.7436438870371587047521915061" or
+    // "1.5e-3".  Digits beyond the requested precision are truncated.
+    pub fn parse(s: &str, frac: usize) -> Option<BigFixed> {
+        let s = s.trim();
+        let (negative, body) = if let Some(rest) = s.strip_prefix('-') {
+            (true, rest)
+        } else if let Some(rest) = s.strip_prefix('+') {
+            (false, rest)
+        } else {
+            (false, s)
+        };
+
+        let (mantissa, exponent) = match body.find(['e', 'E']) {
+            Some(pos) => {
+                match body[pos+1..].parse::<i32>() {
+                    Ok(e) => (&body[..pos], e),
+                    Err(_) => return None,
+                }
+            },
+            None => (body, 0),
+        };
+        if exponent.abs() > 100000 {
+            return None;
+        }
+
+        let mut digits: Vec<u32> = vec![0];
+        let mut ndigits = 0;
+        let mut frac_digits = 0;
+        let mut seen_point = false;
+        for ch in mantissa.chars() {
+            match ch {
+                _ if ch.is_ascii_digit() => {
+                    mul_small_grow(&mut digits, 10, ch as u32 - '0' as u32);
+                    ndigits += 1;
+                    if seen_point {
+                        frac_digits += 1;
+                    }
+                },
+                '.' if !seen_point => seen_point = true,
+                _ => return None,
+            }
+        }
+        if ndigits == 0 {
+            return None;
+        }
+
+        // Scale the integer of all digits by 2^(32*frac) * 10^exp10
+        let mut mag = vec![0; frac];
+        mag.extend_from_slice(&digits);
+        let mut exp10 = exponent - frac_digits;
+        while exp10 > 0 {
+            mul_small_grow(&mut mag, 10, 0);
+            exp10 -= 1;
+        }
+        while exp10 < 0 {
+            let step = if exp10 <= -9 { 9 } else { -exp10 };
+            div_small(&mut mag, 10u32.pow(step as u32));
+            exp10 += step;
+        }
+
+        Some(BigFixed::from_parts(negative, mag, frac))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let mut x = 0.0f64;
+        for (i, &limb) in self.mag.iter().enumerate() {
+            if limb != 0 {
+                x += ldexp(limb as f64, 32 * (i as i32 - self.frac as i32));
+            }
+        }
+        if self.negative { -x } else { x }
+    }
+
+    // Divide by two, exactly except for the lowest bit
+    pub fn half(&self) -> BigFixed {
+        let mut mag = self.mag.clone();
+        shr_bits(&mut mag, 1);
+        BigFixed::from_parts(self.negative, mag, self.frac)
+    }
+
+    fn from_parts(negative: bool, mut mag: Vec<u32>, frac: usize) -> BigFixed {
+        trim(&mut mag, frac + 1);
+        let zero = mag.iter().all(|&limb| limb == 0);
+        BigFixed { negative: negative && !zero, mag: mag, frac: frac }
+    }
+
+    // Both operands at the same (larger) precision
+    fn aligned(&self, other: &BigFixed) -> (BigFixed, BigFixed) {
+        let frac = if self.frac > other.frac { self.frac } else { other.frac };
+        (self.with_precision(frac), other.with_precision(frac))
+    }
+
+    fn signed_add(&self, other: &BigFixed, negate_other: bool) -> BigFixed {
+        let (a, b) = self.aligned(other);
+        let b_negative = b.negative != negate_other;
+        if a.negative == b_negative {
+            BigFixed::from_parts(a.negative, add_mag(&a.mag, &b.mag), a.frac)
+        } else {
+            match cmp_mag(&a.mag, &b.mag) {
+                Ordering::Less => BigFixed::from_parts(b_negative, sub_mag(&b.mag, &a.mag), a.frac),
+                _ => BigFixed::from_parts(a.negative, sub_mag(&a.mag, &b.mag), a.frac),
+            }
+        }
+    }
+}
+
+//----------------------------------------------------------------------------
+
+impl Add<&BigFixed> for &BigFixed {
+    type Output = BigFixed;
+
+    fn add(self, rhs: &BigFixed) -> BigFixed {
+        self.signed_add(rhs, false)
+    }
+}
+
+impl Sub<&BigFixed> for &BigFixed {
+    type Output = BigFixed;
+
+    fn sub(self, rhs: &BigFixed) -> BigFixed {
+        self.signed_add(rhs, true)
+    }
+}
+
+impl Mul<&BigFixed> for &BigFixed {
+    type Output = BigFixed;
+
+    fn mul(self, rhs: &BigFixed) -> BigFixed {
+        let (a, b) = self.aligned(rhs);
+        let product = mul_mag(&a.mag, &b.mag);
+        BigFixed::from_parts(a.negative != b.negative, product[a.frac..].to_vec(), a.frac)
+    }
+}
+
+impl Neg for &BigFixed {
+    type Output = BigFixed;
+
+    fn neg(self) -> BigFixed {
+        BigFixed::from_parts(!self.negative, self.mag.clone(), self.frac)
+    }
+}
+
+impl fmt::Display for BigFixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+
+        // Integer part by repeated division
+        let mut int_part = self.mag[self.frac..].to_vec();
+        let mut int_digits = Vec::new();
+        loop {
+            let r = div_small(&mut int_part, 10);
+            int_digits.push((b'0' + r as u8) as char);
+            if int_part.iter().all(|&limb| limb == 0) {
+                break;
+            }
+        }
+
+        // Fraction part by repeated multiplication, enough digits to
+        // represent every bit unless a precision was requested
+        let mut frac_part = self.mag[..self.frac].to_vec();
+        let ndigits = match f.precision() {
+            Some(p) => p,
+            None => (self.frac as f64 * 32.0 * 2.0f64.log10()).ceil() as usize,
+        };
+        let mut frac_digits: Vec<u8> = Vec::with_capacity(ndigits);
+        for _ in 0..ndigits {
+            let carry = mul_small(&mut frac_part, 10, 0);
+            frac_digits.push(b'0' + carry as u8);
+        }
+
+        // Round the last digit up if anything is left, so that parsing the
+        // digits at the same precision gives back the same number.  The
+        // last digit is worth no more than the last bit, so it can't carry
+        // into the integer part.
+        if f.precision().is_none() {
+            if frac_part.iter().any(|&limb| limb != 0) {
+                let mut k = ndigits;
+                while frac_digits[k-1] == b'9' {
+                    frac_digits[k-1] = b'0';
+                    k -= 1;
+                }
+                frac_digits[k-1] += 1;
+            }
+            while frac_digits.last() == Some(&b'0') {
+                frac_digits.pop();
+            }
+        }
+
+        let mut s = String::new();
+        if self.negative {
+            s.push('-');
+        }
+        s.extend(int_digits.iter().rev());
+        if !frac_digits.is_empty() {
+            s.push('.');
+            s.extend(frac_digits.iter().map(|&digit| digit as char));
+        }
+        f.write_str(&s)
+    }
+}
+
+// x * 2^e, in steps so that very small scales don't flush to zero early
+fn ldexp(mut x: f64, mut e: i32) -> f64 {
+    while e < -512 {
+        x *= 2.0f64.powi(-512);
+        e += 512;
+    }
+    while e > 512 {
+        x *= 2.0f64.powi(512);
+        e -= 512;
+    }
+    x * 2.0f64.powi(e)
+}
+
+//----------------------------------------------------------------------------
+// Unsigned magnitude helpers, little-endian u32 limbs
+
+fn trim(v: &mut Vec<u32>, min_len: usize) {
+    while v.len() > min_len && v[v.len()-1] == 0 {
+        v.pop();
+    }
+    while v.len() < min_len {
+        v.push(0);
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    let n = if a.len() > b.len() { a.len() } else { b.len() };
+    for i in (0..n).rev() {
+        let x = if i < a.len() { a[i] } else { 0 };
+        let y = if i < b.len() { b[i] } else { 0 };
+        if x != y {
+            return x.cmp(&y);
+        }
+    }
+    Ordering::Equal
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let n = if a.len() > b.len() { a.len() } else { b.len() };
+    let mut sum = Vec::with_capacity(n + 1);
+    let mut carry = 0u64;
+    for i in 0..n {
+        let x = if i < a.len() { a[i] as u64 } else { 0 };
+        let y = if i < b.len() { b[i] as u64 } else { 0 };
+        let t = x + y + carry;
+        sum.push(t as u32);
+        carry = t >> 32;
+    }
+    sum.push(carry as u32);
+    sum
+}
+
+// Requires a >= b
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut diff = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for i in 0..a.len() {
+        let y = if i < b.len() { b[i] as i64 } else { 0 };
+        let mut t = a[i] as i64 - y - borrow;
+        if t < 0 {
+            t += 1i64 << 32;
+            borrow = 1;
+        } else {
+            borrow = 0;
+        }
+        diff.push(t as u32);
+    }
+    diff
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut product = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        if x == 0 {
+            continue;
+        }
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let t = (x as u64) * (y as u64) + product[i+j] as u64 + carry;
+            product[i+j] = t as u32;
+            carry = t >> 32;
+        }
+        let mut k = i + b.len();
+        while carry != 0 {
+            let t = product[k] as u64 + carry;
+            product[k] = t as u32;
+            carry = t >> 32;
+            k += 1;
+        }
+    }
+    product
+}
+
+// v = v*m + a in place, returning the carry out of the top limb
+fn mul_small(v: &mut [u32], m: u32, a: u32) -> u32 {
+    let mut carry = a as u64;
+    for limb in v.iter_mut() {
+        let t = (*limb as u64) * (m as u64) + carry;
+        *limb = t as u32;
+        carry = t >> 32;
+    }
+    carry as u32
+}
+
+// As mul_small, but growing v to hold the carry
+fn mul_small_grow(v: &mut Vec<u32>, m: u32, a: u32) {
+    let carry = mul_small(v, m, a);
+    if carry != 0 {
+        v.push(carry);
+    }
+}
+
+// v = v/d in place, returning the remainder
+fn div_small(v: &mut [u32], d: u32) -> u32 {
+    let mut rem = 0u64;
+    for limb in v.iter_mut().rev() {
+        let t = (rem << 32) | *limb as u64;
+        *limb = (t / d as u64) as u32;
+        rem = t % d as u64;
+    }
+    rem as u32
+}
+
+fn shl_bits(v: &mut Vec<u32>, n: usize) {
+    let limbs = n / 32;
+    let bits = n % 32;
+    if bits != 0 {
+        let mut carry = 0u32;
+        for limb in v.iter_mut() {
+            let t = *limb;
+            *limb = (t << bits) | carry;
+            carry = t >> (32 - bits);
+        }
+        v.push(carry);
+    }
+    if limbs != 0 {
+        let mut shifted = vec![0; limbs];
+        shifted.extend_from_slice(v);
+        *v = shifted;
+    }
+}
+
+fn shr_bits(v: &mut Vec<u32>, n: usize) {
+    let limbs = n / 32;
+    let bits = n % 32;
+    if limbs >= v.len() {
+        *v = vec![0];
+        return;
+    }
+    if limbs != 0 {
+        *v = v[limbs..].to_vec();
+    }
+    if bits != 0 {
+        let mut carry = 0u32;
+        for limb in v.iter_mut().rev() {
+            let t = *limb;
+            *limb = (t >> bits) | carry;
+            carry = t << (32 - bits);
+        }
+    }
+}
+
+//----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::BigFixed;
+
+    fn parse(s: &str, frac: usize) -> BigFixed {
+        BigFixed::parse(s, frac).unwrap()
+    }
+
+    #[test]
+    fn parse_reads_signs_and_exponents() {
+        assert_eq!(parse("1.5e-3", 2), parse("0.0015", 2));
+        assert_eq!(parse("-2.5E+2", 2), BigFixed::from_f64(-250.0, 2));
+        assert_eq!(parse("+0.5", 2), BigFixed::from_f64(0.5, 2));
+        assert_eq!(parse("1e3", 1), BigFixed::from_f64(1000.0, 1));
+        assert_eq!(parse("12.", 1), BigFixed::from_f64(12.0, 1));
+        assert_eq!(parse(".25", 1), BigFixed::from_f64(0.25, 1));
+        assert!(!parse("-0.000", 2).is_negative());
+        for s in ["", "-", "+", ".", "1.2.3", "1e", "1e+", "abc", "1,5", "--1", "1e999999"].iter() {
+            assert!(BigFixed::parse(s, 2).is_none(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn parse_truncates_beyond_precision() {
+        // One limb after the point holds multiples of 2^-32
+        let ulp = 2.0f64.powi(-32);
+        assert_eq!(parse("1e-10", 1), BigFixed::zero(1));
+        assert_eq!(parse("0.9999999999", 1).to_f64(), 1.0 - ulp);
+        assert_eq!(parse("-0.9999999999", 1).to_f64(), -(1.0 - ulp));
+    }
+
+    #[test]
+    fn display_prints_enough_digits() {
+        assert_eq!(BigFixed::zero(2).to_string(), "0");
+        assert_eq!(BigFixed::from_f64(3.0, 1).to_string(), "3");
+        assert_eq!(BigFixed::from_f64(-0.375, 2).to_string(), "-0.375");
+        // 2^-32 is 0.00000000023283..., rounded up in the tenth digit
+        assert_eq!(BigFixed::from_f64(2.0f64.powi(-32), 1).to_string(), "0.0000000003");
+        assert_eq!(BigFixed::from_f64(1.0 - 2.0f64.powi(-32), 1).to_string(), "0.9999999998");
+        assert_eq!(format!("{:.5}", BigFixed::from_f64(0.1, 2)), "0.10000");
+        assert_eq!(format!("{:.2}", BigFixed::from_f64(-7.0, 1)), "-7.00");
+        assert_eq!(parse("123456789012345678901234567890", 1).to_string(), "123456789012345678901234567890");
+    }
+
+    #[test]
+    fn decimals_round_trip() {
+        for s in ["-0.7436438870371587047521915061", "0.1", "-1.25", "2", "1e-40"].iter() {
+            for frac in 1..8 {
+                let x = parse(s, frac);
+                assert_eq!(parse(&x.to_string(), frac), x, "{} at {} limbs", s, frac);
+            }
+        }
+
+        // Products of thirds, which fill every limb
+        for frac in 1..8 {
+            let third = parse("0.3333333333333333333333333333333333333333333333333333333333333333333333333333", frac);
+            let mut x = BigFixed::from_f64(-1.0, frac);
+            for n in 0..200 {
+                x = &(&x * &third) + &BigFixed::from_f64(1.0 / (n + 1) as f64, frac);
+                assert_eq!(parse(&x.to_string(), frac), x, "{} at {} limbs", x, frac);
+            }
+        }
+    }
+
+    #[test]
+    fn from_f64_is_exact() {
+        let tiny = 2.0f64.powi(-1074);
+        for &x in [1.0, -1.75, 0.1, -123456.789, 1e-300, -1e300, tiny, -3.0 * tiny, 2.0f64.powi(-1022)].iter() {
+            let big = BigFixed::from_f64(x, 34);
+            assert_eq!(big.to_f64(), x);
+            assert_eq!(big.is_negative(), x < 0.0);
+        }
+        for &x in [0.0, -0.0, f64::NAN, f64::INFINITY].iter() {
+            assert_eq!(BigFixed::from_f64(x, 2), BigFixed::zero(2));
+        }
+
+        // Too small for the precision, so nothing is left, not even the sign
+        let lost = BigFixed::from_f64(-1e-30, 2);
+        assert_eq!(lost, BigFixed::zero(2));
+        assert!(!lost.is_negative());
+    }
+
+    #[test]
+    fn arithmetic_matches_f64_when_exact() {
+        let values = [1.5, -2.25, 0.0, 1024.0, -0.0078125, 3.0 * 2.0f64.powi(-40)];
+        for &a in values.iter() {
+            for &b in values.iter() {
+                let (x, y) = (BigFixed::from_f64(a, 3), BigFixed::from_f64(b, 3));
+                assert_eq!((&x + &y).to_f64(), a + b);
+                assert_eq!((&x - &y).to_f64(), a - b);
+                assert_eq!((&x * &y).to_f64(), a * b);
+            }
+            assert_eq!((-&BigFixed::from_f64(a, 3)).to_f64(), -a);
+        }
+        let x = BigFixed::from_f64(-5.5, 2);
+        assert_eq!(&x - &x, BigFixed::zero(2));
+        assert!(!(&x - &x).is_negative());
+        assert_eq!(x.half().to_f64(), -2.75);
+    }
+
+    #[test]
+    fn mixed_precisions_use_the_larger() {
+        let fine = BigFixed::from_f64(2.0f64.powi(-60), 2);
+        let coarse = BigFixed::from_f64(1.0, 1);
+        let sum = &coarse + &fine;
+        assert_eq!(sum.precision(), 2);
+        assert_eq!(&sum - &fine, coarse.with_precision(2));
+        assert_eq!(sum.with_precision(1), coarse);
+    }
+
+    #[test]
+    fn mul_truncates_toward_zero() {
+        let ulp = 2.0f64.powi(-32);
+        let x = BigFixed::from_f64(ulp, 1);
+        assert_eq!(&x * &x, BigFixed::zero(1));
+        let product = &(-&x) * &x;
+        assert_eq!(product, BigFixed::zero(1));
+        assert!(!product.is_negative());
+
+        // (1 + 2^-32)^2 = 1 + 2^-31 + 2^-64, which loses the last term
+        let y = BigFixed::from_f64(1.0 + ulp, 1);
+        assert_eq!((&y * &y).to_f64(), 1.0 + 2.0 * ulp);
+        let z = -&y;
+        assert_eq!((&z * &y).to_f64(), -(1.0 + 2.0 * ulp));
+    }
+}
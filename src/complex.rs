@@ -0,0 +1,172 @@
+//============================================================================
+//
+// A simple Mandelbrot image generator in Rust
+//
+// Complex numbers in double precision
+//
+// Copyright (c) 2014 Gavin Baker <gavinb@antonym.org>
+// Published under the MIT license
+//
+//============================================================================
+
+#![allow(dead_code)]
+
+use std::ops::{Add, Sub, Mul, Div, Neg};
+
+//----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re: re, im: im }
+    }
+
+    pub fn zero() -> Complex {
+        Complex { re: 0.0, im: 0.0 }
+    }
+
+    // Squared magnitude, cheaper than norm() for bailout tests
+    pub fn norm_sqr(&self) -> f64 {
+        self.re*self.re + self.im*self.im
+    }
+
+    pub fn norm(&self) -> f64 {
+        self.norm_sqr().sqrt()
+    }
+
+    pub fn scale(&self, k: f64) -> Complex {
+        Complex { re: self.re*k, im: self.im*k }
+    }
+
+    pub fn conj(&self) -> Complex {
+        Complex { re: self.re, im: -self.im }
+    }
+
+    pub fn arg(&self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn from_polar(r: f64, theta: f64) -> Complex {
+        Complex { re: r*theta.cos(), im: r*theta.sin() }
+    }
+
+    pub fn exp(&self) -> Complex {
+        Complex::from_polar(self.re.exp(), self.im)
+    }
+
+    // Principal value of the natural logarithm
+    pub fn ln(&self) -> Complex {
+        Complex { re: self.norm().ln(), im: self.arg() }
+    }
+
+    pub fn sqrt(&self) -> Complex {
+        Complex::from_polar(self.norm().sqrt(), self.arg()*0.5)
+    }
+
+    pub fn sin(&self) -> Complex {
+        Complex { re: self.re.sin()*self.im.cosh(), im: self.re.cos()*self.im.sinh() }
+    }
+
+    pub fn cos(&self) -> Complex {
+        Complex { re: self.re.cos()*self.im.cosh(), im: -self.re.sin()*self.im.sinh() }
+    }
+
+    pub fn sinh(&self) -> Complex {
+        Complex { re: self.re.sinh()*self.im.cos(), im: self.re.cosh()*self.im.sin() }
+    }
+
+    pub fn cosh(&self) -> Complex {
+        Complex { re: self.re.cosh()*self.im.cos(), im: self.re.sinh()*self.im.sin() }
+    }
+
+    // Integer powers by repeated squaring
+    pub fn powi(&self, mut n: u32) -> Complex {
+        let mut base = *self;
+        let mut result = Complex::new(1.0, 0.0);
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            n >>= 1;
+        }
+        result
+    }
+
+    // Real powers, using the polar form unless the exponent is a small
+    // whole number.  0^d is taken as 0 for every d, so that negative
+    // exponents can still start an orbit from zero.
+    pub fn powf(&self, d: f64) -> Complex {
+        if (0.0..=64.0).contains(&d) && d == d.floor() {
+            return self.powi(d as u32);
+        }
+        if self.re == 0.0 && self.im == 0.0 {
+            return Complex::zero();
+        }
+        Complex::from_polar(self.norm().powf(d), self.arg()*d)
+    }
+
+    // Complex powers, exp(w*ln(z)), again with 0^w taken as 0
+    pub fn powc(&self, w: Complex) -> Complex {
+        if w.im == 0.0 {
+            return self.powf(w.re);
+        }
+        if self.re == 0.0 && self.im == 0.0 {
+            return Complex::zero();
+        }
+        (w * self.ln()).exp()
+    }
+}
+
+//----------------------------------------------------------------------------
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, rhs: Complex) -> Complex {
+        Complex { re: self.re + rhs.re, im: self.im + rhs.im }
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex { re: self.re - rhs.re, im: self.im - rhs.im }
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, rhs: Complex) -> Complex {
+        Complex { re: self.re*rhs.re - self.im*rhs.im,
+                  im: self.re*rhs.im + self.im*rhs.re }
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    fn div(self, rhs: Complex) -> Complex {
+        let d = rhs.norm_sqr();
+        Complex { re: (self.re*rhs.re + self.im*rhs.im) / d,
+                  im: (self.im*rhs.re - self.re*rhs.im) / d }
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+
+    fn neg(self) -> Complex {
+        Complex { re: -self.re, im: -self.im }
+    }
+}
+
+//----------------------------------------------------------------------------
@@ -9,26 +9,614 @@
 //
 //============================================================================
 
+use std::collections::VecDeque;
+use std::sync::Arc;
 use std::sync::mpsc::{Sender, Receiver};
 use std::vec::Vec;
 
-use protocol::{RenderType, EngineStatus, EngineCommand};
+use bignum;
+use bignum::BigFixed;
+use complex::Complex;
+use expr::Expression;
+use formula::{FractalFormula, EscapeTime, ExpressionFormula};
+use newton::Polynomial;
+use perturb;
+use perturb::{ReferenceOrbit, Perturbed, SeriesApproximation};
+use protocol::{RenderType, EngineStatus, EngineCommand, EngineError, Fractal, Variant, Colouring};
+use protocol::{RenderStats, RenderParams, Strategy, Supersampling};
+use job;
+use job::{Job, Cancelled};
+use simd;
+use tiles;
 
 static PREVIEW_WIDTH: i32 = 256;
 static PREVIEW_HEIGHT: i32 = 256;
 
+pub static DEFAULT_CENTRE_RE: f64 = -0.4;
+pub static DEFAULT_CENTRE_IM: f64 = 0.0;
+pub static DEFAULT_WIDTH: f64 = 2.0;
+
+// Switch to perturbation once pixels are closer together than this, as
+// plain f64 can no longer tell neighbouring values of c apart
+static DEEP_ZOOM_PIXEL_SIZE: f64 = 1.0e-13;
+
+// Precision carried beyond the pixel spacing for the viewport centre
+static GUARD_BITS: u32 = 64;
+
+// Limit on reference orbits used to repair glitches in one frame
+static MAX_REFERENCES: usize = 32;
+
+// Probe points along each side of the grid that checks the series
+static SERIES_PROBES: u32 = 5;
+
+// Bailout radius when estimating distances, which are only accurate once
+// |z| is large
+static DISTANCE_BAILOUT: f64 = 1000.0;
+
+// Iterations added to the limit for each tenfold zoom past the default
+// width, when scaling it automatically
+static AUTO_ITERATIONS_PER_DECADE: f64 = 500.0;
+
+// Squared distance between two points of an orbit for it to count as
+// having come back round, a few ulps at the scale of the set
+static PERIOD_TOLERANCE: f64 = 1.0e-30;
+
+// Palette entries in one colour cycle
+static PALETTE_CYCLE: usize = 580;
+
+// Rectangles of up to this many pixels are iterated in full rather than
+// subdivided further
+static SUBDIVIDE_MIN_AREA: u32 = 16;
+
+// How far from a whole number of pixels a pan may be and still reuse the
+// last frame
+static PAN_TOLERANCE: f64 = 1.0e-3;
+
+// Adam7 passes for progressive renders: the first pixel and spacing of the
+// pixels in each pass, and the size of the block each one stands in for
+// until later passes fill it in
+static PROGRESSIVE_PASSES: [(u32, u32, u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8, 8, 8),
+    (4, 0, 8, 8, 4, 8),
+    (0, 4, 4, 8, 4, 4),
+    (2, 0, 4, 4, 2, 4),
+    (0, 2, 2, 4, 2, 2),
+    (1, 0, 2, 2, 1, 2),
+    (0, 1, 1, 2, 1, 1),
+];
+
+// Neighbouring pixels that differ by more than this in any colour channel,
+// or in iteration count, are supersampled by adaptive supersampling
+static ADAPTIVE_COLOUR_STEP: i32 = 32;
+static ADAPTIVE_ITERATION_STEP: i64 = 16;
+
+// Newton steps over which a root's colour fades to its darkest
+static NEWTON_SHADE_STEPS: f32 = 40.0;
+
+// Rotated grid sample offsets, in pixels
+static RGSS_OFFSETS: [(f64, f64); 4] = [(-0.125, -0.375), (0.375, -0.125), (0.125, 0.375), (-0.375, 0.125)];
+
 //----------------------------------------------------------------------------
 
 type RGB8 = (u8, u8, u8);
 
+// Colours laid out as the bytes of an RGB image
+fn rgb_bytes(colours: Vec<RGB8>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(colours.len() * 3);
+    for (r, g, b) in colours {
+        bytes.push(r);
+        bytes.push(g);
+        bytes.push(b);
+    }
+    bytes
+}
+
+// Convert hue (in degrees), saturation and value to RGB
+fn hsv_to_rgb(h: f32, s: f32, v: f32) -> RGB8 {
+    let c = v * s; // chroma
+    let hp = h/60.0;
+    let x = c * (1.0-(hp % 2.0 - 1.0).abs());
+    let (r,g,b) = if (0.0..1.0).contains(&hp) {
+        (c, x, 0.0)
+    } else if (1.0..2.0).contains(&hp) {
+        (x, c, 0.0)
+    } else if (2.0..3.0).contains(&hp) {
+        (0.0, c, x)
+    } else if (3.0..4.0).contains(&hp) {
+        (0.0, x, c)
+    } else if (4.0..5.0).contains(&hp) {
+        (x, 0.0, c)
+    } else if (5.0..6.0).contains(&hp) {
+        (c, 0.0, x)
+    } else {
+        (0.0, 0.0, 0.0)
+    };
+    let m = v-c;
+    (((r+m)*255.0) as u8, ((g+m)*255.0) as u8, ((b+m)*255.0) as u8)
+}
+
+// Colour of the root Newton's method takes z0 to, or black if it never
+// gets to one
+fn newton_colour(poly: &Polynomial, roots: &[Complex], z0: Complex, max_iteration: u32) -> RGB8 {
+    match poly.newton(roots, z0, max_iteration) {
+        (Some(k), steps) => {
+            let hue = 360.0 * k as f32 / roots.len() as f32;
+            let shade = 1.0 - (steps as f32 / NEWTON_SHADE_STEPS).min(0.8);
+            hsv_to_rgb(hue, 0.8, shade)
+        },
+        (None, _) => (0, 0, 0),
+    }
+}
+
+// sRGB component to linear light
+fn srgb_to_linear(c: u8) -> f64 {
+    let c = c as f64 / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+// Linear light back to an sRGB component
+fn linear_to_srgb(l: f64) -> u8 {
+    let c = if l <= 0.0031308 { l * 12.92 } else { 1.055 * l.powf(1.0 / 2.4) - 0.055 };
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+// Pseudo random number in [0, 1) that only depends on the pixel and the
+// sample, so that jittered renders come out the same every time
+fn jitter(x: u32, y: u32, sample: u32) -> f64 {
+    // SplitMix64 finaliser
+    let mut h = ((x as u64) << 40) ^ ((y as u64) << 16) ^ (sample as u64);
+    h = h.wrapping_add(0x9e3779b97f4a7c15);
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^= h >> 31;
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// True inside the main cardioid or the period 2 bulb of the Mandelbrot set,
+// where orbits are known never to escape
+fn in_main_bulbs(c: Complex) -> bool {
+    let x = c.re - 0.25;
+    let y2 = c.im*c.im;
+    let q = x*x + y2;
+    if q*(q + x) < 0.25*y2 {
+        return true;
+    }
+    let x = c.re + 1.0;
+    x*x + y2 < 0.0625
+}
+
+// About a tenth of `size` pixels, rounded to a whole number of pixels at
+// the preview size too, unless that would be coarser than the step itself
+fn pan_step(size: u32, preview: u32) -> f64 {
+    let step = (size as f64 * 0.1).round().max(1.0);
+    let unit = (size / gcd(size, preview)) as f64;
+    if unit <= step { (step / unit).round() * unit } else { step }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+// Old line, if any, standing in for each column and each row of a zoomed
+// frame
+type ZoomLines = (Vec<Option<u32>>, Vec<Option<u32>>);
+
+// The old line standing in for each of `count` new ones, when new line i
+// falls at offset + i*scale in old line numbers.  Each old line goes to the
+// new line nearest it, as long as it is within half a new line of it.
+fn nearest_lines(count: u32, offset: f64, scale: f64) -> Vec<Option<u32>> {
+    let mut best: Vec<Option<(u32, f64)>> = vec![None; count as usize];
+    for i in 0..count {
+        let position = offset + i as f64 * scale;
+        let line = position.round();
+        let error = (position - line).abs();
+        if line < 0.0 || line >= count as f64 || error > 0.5 * scale.abs() {
+            continue;
+        }
+        let slot = &mut best[line as usize];
+        match *slot {
+            Some((_, e)) if e <= error => (),
+            _ => *slot = Some((i, error)),
+        }
+    }
+
+    let mut lines = vec![None; count as usize];
+    for (line, entry) in best.iter().enumerate() {
+        if let Some((i, _)) = *entry {
+            lines[i as usize] = Some(line as u32);
+        }
+    }
+    lines
+}
+
+// As nearest_lines, with each line that has no old line standing in for it
+// given that of the nearest one that does.  At least one must.
+fn fill_lines(lines: &[Option<u32>]) -> Vec<u32> {
+    let known: Vec<(usize, u32)> = lines.iter().enumerate()
+        .filter_map(|(i, line)| line.map(|line| (i, line))).collect();
+    let mut next = 0;
+    (0..lines.len()).map(|i| {
+        while next + 1 < known.len() && known[next + 1].0 <= i {
+            next += 1;
+        }
+        // Between known[next] and known[next + 1], or before the first
+        let (before, line) = known[next];
+        match known.get(next + 1) {
+            Some(&(after, later)) if i > before && after - i < i - before => later,
+            _ => line,
+        }
+    }).collect()
+}
+
+// Where the orbit of one pixel ended up
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    iteration: u32,
+    // |z|^2 at the last iteration
+    norm_sqr: f64,
+    // Distance to the set, if the derivative was tracked
+    distance: Option<f64>,
+}
+
+impl Sample {
+
+    // From the last z, and dz/dc there if it was tracked
+    fn new(iteration: u32, z: Complex, dz: Option<Complex>, max_iteration: u32) -> Sample {
+        let norm_sqr = z.norm_sqr();
+
+        // Exterior distance estimate |z| ln|z| / |dz/dc|; points that never
+        // escaped are taken to be in the set
+        let distance = dz.map(|dz| {
+            if iteration < max_iteration {
+                let r = norm_sqr.sqrt();
+                r * r.ln() / dz.norm()
+            } else {
+                0.0
+            }
+        });
+
+        Sample { iteration: iteration, norm_sqr: norm_sqr, distance: distance }
+    }
+}
+
+// Where a frame samples each pixel, as an offset in pixels from the point
+// a plain render takes
+#[derive(Clone, Copy, PartialEq)]
+enum SamplePoint {
+    Fixed(f64, f64),
+    // Somewhere in cell (i, j) of an n x n grid over the pixel, differing
+    // from one pixel to the next
+    Jittered { i: u32, j: u32, n: u32 },
+}
+
+impl SamplePoint {
+    fn offset(&self, x: u32, y: u32) -> (f64, f64) {
+        match *self {
+            SamplePoint::Fixed(dx, dy) => (dx, dy),
+            SamplePoint::Jittered { i, j, n } => {
+                let k = 2 * (j*n + i);
+                ((i as f64 + jitter(x, y, k)) / n as f64 - 0.5,
+                 (j as f64 + jitter(x, y, k + 1)) / n as f64 - 0.5)
+            },
+        }
+    }
+}
+
+// Points to sample within each pixel
+fn sample_points(supersampling: Supersampling) -> Vec<SamplePoint> {
+    let cells = |n: u32| (0..n*n).map(move |k| (k % n, k / n));
+    match supersampling {
+        Supersampling::None | Supersampling::Grid(0) | Supersampling::Jittered(0) => {
+            vec![SamplePoint::Fixed(0.0, 0.0)]
+        },
+        Supersampling::Grid(n) => cells(n).map(|(i, j)| {
+            SamplePoint::Fixed((i as f64 + 0.5) / n as f64 - 0.5, (j as f64 + 0.5) / n as f64 - 0.5)
+        }).collect(),
+        Supersampling::RotatedGrid => RGSS_OFFSETS.iter().map(|&(dx, dy)| SamplePoint::Fixed(dx, dy)).collect(),
+        Supersampling::Jittered(n) => cells(n).map(|(i, j)| SamplePoint::Jittered { i: i, j: j, n: n }).collect(),
+    }
+}
+
+// Pixel geometry of one render, small enough to copy to each worker.  The
+// pixels rendered are a grid of columns x rows, picking every step_x'th
+// pixel of the image from origin_x, and likewise down.
+#[derive(Clone, Copy)]
+struct Frame {
+    centre: Complex,
+    width: f64,
+    height: f64,
+    pixels_x: u32,
+    pixels_y: u32,
+    origin_x: u32,
+    origin_y: u32,
+    step_x: u32,
+    step_y: u32,
+    columns: u32,
+    rows: u32,
+    point: SamplePoint,
+}
+
+impl Frame {
+
+    // Every step'th pixel from (x0, y0)
+    fn subgrid(&self, x0: u32, y0: u32, step_x: u32, step_y: u32) -> Frame {
+        let count = |start: u32, step: u32, pixels: u32| {
+            if start < pixels { (pixels - start).div_ceil(step) } else { 0 }
+        };
+        Frame {
+            origin_x: x0,
+            origin_y: y0,
+            step_x: step_x,
+            step_y: step_y,
+            columns: count(x0, step_x, self.pixels_x),
+            rows: count(y0, step_y, self.pixels_y),
+            .. *self
+        }
+    }
+
+    // The columns x rows block of pixels from (x0, y0)
+    fn window(&self, x0: u32, y0: u32, columns: u32, rows: u32) -> Frame {
+        Frame {
+            columns: columns,
+            rows: rows,
+            .. self.subgrid(x0, y0, 1, 1)
+        }
+    }
+
+    // The same pixels, sampled elsewhere within each one
+    fn sampled_at(&self, point: SamplePoint) -> Frame {
+        Frame { point: point, .. *self }
+    }
+
+    // Whether each pixel is sampled where a plain render would
+    fn is_plain(&self) -> bool {
+        self.point == SamplePoint::Fixed(0.0, 0.0)
+    }
+
+    // Whether the grid covers every pixel of the image
+    fn is_whole(&self) -> bool {
+        self.step_x == 1 && self.step_y == 1 &&
+            self.columns == self.pixels_x && self.rows == self.pixels_y
+    }
+
+    // Image pixel at grid position (x, y)
+    fn pixel(&self, x: u32, y: u32) -> (u32, u32) {
+        (self.origin_x + x*self.step_x, self.origin_y + y*self.step_y)
+    }
+
+    // Width of an image pixel in cspace
+    fn pixel_size(&self) -> f64 {
+        (self.width / self.pixels_x as f64).abs()
+    }
+
+    // Rescale grid coord (x,y) into an offset from the centre in cspace
+    fn scale_coords(&self, x: u32, y: u32) -> Complex {
+        let (x, y) = self.pixel(x, y);
+        let (dx, dy) = self.point.offset(x, y);
+        let xx = ((x as f64 + dx) / (self.pixels_x as f64) - 0.5) * self.width;
+        let yy = ((y as f64 + dy) / (self.pixels_y as f64) - 0.5) * self.height;
+
+        Complex::new(xx, yy)
+    }
+}
+
+// Settings for iterating pixels directly, shared by the workers
+struct Escape {
+    formula: Arc<dyn FractalFormula>,
+    bailout: f64,
+    // Follow the derivative, for distance estimates
+    track: bool,
+    // Skip the main cardioid and period 2 bulb
+    bulbs: bool,
+    // Iterate several pixels at a time with the z^2 + c kernel
+    vector: bool,
+    // Fill rectangles with uniform borders instead of iterating them
+    subdivide: bool,
+    // Whether rectangles of escaped points may be filled, as well as those
+    // inside the set; not if colours vary within an iteration band
+    fill_escaped: bool,
+    max_iteration: u32,
+}
+
+// Results for one tile as it is subdivided, by grid position
+struct Block {
+    x0: u32,
+    y0: u32,
+    width: u32,
+    values: Vec<Option<(Sample, u64)>>,
+}
+
+impl Block {
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.y0)*self.width + x - self.x0) as usize
+    }
+}
+
+// Samples of the last view rendered, kept so that after a pan the ones
+// still in view can be shifted across rather than worked out again
+struct Kept {
+    centre_re: BigFixed,
+    centre_im: BigFixed,
+    frame: Frame,
+    max_iteration: u32,
+    // By image pixel; a progressive render fills them in pass by pass
+    samples: Vec<Option<Sample>>,
+}
+
+impl Kept {
+
+    // Sample of old pixel (x, y) reused in a frame iterated to
+    // max_iteration; points that stayed inside the set still count as inside
+    fn reused(&self, x: u32, y: u32, max_iteration: u32) -> Sample {
+        let mut sample = self.samples[(y*self.frame.pixels_x + x) as usize].unwrap();
+        if sample.iteration == self.max_iteration {
+            sample.iteration = max_iteration;
+        }
+        sample
+    }
+}
+
+impl Escape {
+
+    // Where the orbit of `pixel` ends up, and the number of iterations
+    // skipped by spotting early that it is inside
+    fn iterate(&self, pixel: Complex) -> (Sample, u64) {
+        let formula = &*self.formula;
+        let max_iteration = self.max_iteration;
+
+        let (mut z, c) = formula.init(pixel);
+        let mut dz = if self.track { Some(formula.init_derivative()) } else { None };
+        let mut iteration = 0;
+
+        if self.bulbs && in_main_bulbs(pixel) {
+            return (Sample::new(max_iteration, z, dz, max_iteration), max_iteration as u64);
+        }
+
+        // Brent's cycle detection: compare against a saved point,
+        // saving a new one after twice as many steps each time
+        let mut cycle_start = z;
+        let mut cycle_length = 1;
+        let mut steps = 0;
+
+        // Iterate!
+        while !formula.bailout(z, self.bailout) && (iteration < max_iteration) {
+            dz = dz.and_then(|dz| formula.derivative(z, dz));
+            z = formula.step(z, c);
+
+            iteration += 1;
+
+            if (z - cycle_start).norm_sqr() < PERIOD_TOLERANCE {
+                let saved = (max_iteration - iteration) as u64;
+                return (Sample::new(max_iteration, z, dz, max_iteration), saved);
+            }
+            steps += 1;
+            if steps == cycle_length {
+                cycle_start = z;
+                cycle_length *= 2;
+                steps = 0;
+            }
+        }
+
+        (Sample::new(iteration, z, dz, max_iteration), 0)
+    }
+
+    // Mariani-Silver subdivision of the tile from (x0, y0) up to (x1, y1)
+    fn subdivide_block(&self, frame: &Frame, x0: u32, y0: u32, x1: u32, y1: u32,
+                       values: &mut Vec<(Sample, u64)>) {
+        let mut block = Block {
+            x0: x0,
+            y0: y0,
+            width: x1 - x0,
+            values: vec![None; ((x1 - x0) * (y1 - y0)) as usize],
+        };
+        self.subdivide(frame, &mut block, x0, y0, x1 - 1, y1 - 1);
+        values.extend(block.values.into_iter().map(|value| value.unwrap()));
+    }
+
+    // Fill in the rectangle with corners (x0, y0) and (x1, y1) inclusive,
+    // whose border may already be partly done
+    fn subdivide(&self, frame: &Frame, block: &mut Block, x0: u32, y0: u32, x1: u32, y1: u32) {
+        let mut border: Vec<(u32, u32)> = (x0..x1+1).map(|x| (x, y0)).collect();
+        border.extend((x0..x1+1).map(|x| (x, y1)));
+        border.extend((y0+1..y1).map(|y| (x0, y)));
+        border.extend((y0+1..y1).map(|y| (x1, y)));
+        self.compute(frame, block, &border);
+        if x1 - x0 < 2 || y1 - y0 < 2 {
+            return;
+        }
+
+        let corner = block.values[block.index(x0, y0)].unwrap().0;
+        let iteration = |x: u32, y: u32| block.values[block.index(x, y)].unwrap().0.iteration;
+        let uniform = (x0..x1+1).all(|x| iteration(x, y0) == corner.iteration && iteration(x, y1) == corner.iteration) &&
+            (y0+1..y1).all(|y| iteration(x0, y) == corner.iteration && iteration(x1, y) == corner.iteration);
+
+        if uniform && (corner.iteration == self.max_iteration || self.fill_escaped) {
+            for y in y0+1..y1 {
+                for x in x0+1..x1 {
+                    let i = block.index(x, y);
+                    block.values[i] = Some((corner, corner.iteration as u64));
+                }
+            }
+        } else if (x1 - x0 + 1) * (y1 - y0 + 1) <= SUBDIVIDE_MIN_AREA {
+            let inside: Vec<(u32, u32)> = (y0+1..y1).flat_map(|y| (x0+1..x1).map(move |x| (x, y))).collect();
+            self.compute(frame, block, &inside);
+        } else if x1 - x0 >= y1 - y0 {
+            let xm = (x0 + x1) / 2;
+            self.subdivide(frame, block, x0, y0, xm, y1);
+            self.subdivide(frame, block, xm, y0, x1, y1);
+        } else {
+            let ym = (y0 + y1) / 2;
+            self.subdivide(frame, block, x0, y0, x1, ym);
+            self.subdivide(frame, block, x0, ym, x1, y1);
+        }
+    }
+
+    // Iterate those of the pixels that are not already done
+    fn compute(&self, frame: &Frame, block: &mut Block, pixels: &[(u32, u32)]) {
+        let todo: Vec<(u32, u32)> = pixels.iter().filter(|&&(x, y)| block.values[block.index(x, y)].is_none()).cloned().collect();
+        let mut values = Vec::with_capacity(todo.len());
+        self.iterate_pixels(frame, &todo, &mut values);
+        for (&(x, y), value) in todo.iter().zip(values) {
+            let i = block.index(x, y);
+            block.values[i] = Some(value);
+        }
+    }
+
+    // Iterate the pixels x0..x1 of row y, several at a time if possible
+    fn iterate_span(&self, frame: &Frame, y: u32, x0: u32, x1: u32, values: &mut Vec<(Sample, u64)>) {
+        let pixels: Vec<(u32, u32)> = (x0..x1).map(|x| (x, y)).collect();
+        self.iterate_pixels(frame, &pixels, values);
+    }
+
+    // Iterate the pixels at the given grid positions, several at a time if
+    // possible
+    fn iterate_pixels(&self, frame: &Frame, pixels: &[(u32, u32)], values: &mut Vec<(Sample, u64)>) {
+        if !self.vector {
+            for &(x, y) in pixels {
+                values.push(self.iterate(frame.centre + frame.scale_coords(x, y)));
+            }
+            return;
+        }
+
+        let npixels = pixels.len();
+        let mut z0 = Vec::with_capacity(npixels);
+        let mut c = Vec::with_capacity(npixels);
+        let mut inside = Vec::with_capacity(npixels);
+        for &(x, y) in pixels {
+            let pixel = frame.centre + frame.scale_coords(x, y);
+            let (z, k) = self.formula.init(pixel);
+            z0.push((z.re, z.im));
+            c.push((k.re, k.im));
+            inside.push(self.bulbs && in_main_bulbs(pixel));
+        }
+
+        for orbit in simd::iterate(&z0, &c, &inside, self.bailout, PERIOD_TOLERANCE, self.max_iteration) {
+            let z = Complex::new(orbit.z_re, orbit.z_im);
+            values.push((Sample::new(orbit.iteration, z, None, self.max_iteration), orbit.saved));
+        }
+    }
+}
+
 pub struct MandelEngine {
     buffer_width: u32,
     buffer_height: u32,
     palette: Vec<RGB8>,
-    re0: f32,
-    re1: f32,
-    im0: f32,
-    im1: f32,
+    centre_re: BigFixed,
+    centre_im: BigFixed,
+    width: f64,
+    height: f64,
+    fractal: Fractal,
+    exponent: f64,
+    variant: Variant,
+    expression: Option<Expression>,
+    formula: Arc<dyn FractalFormula>,
+    custom_formula: bool,
+    colouring: Colouring,
+    export_distance: bool,
+    params: RenderParams,
+    pool: tiles::Pool,
+    strategy: Strategy,
+    kept: Option<Kept>,
 }
 
 impl MandelEngine {
@@ -37,106 +625,340 @@ impl MandelEngine {
 
         // Init palette using hue sweep in HSV colour space
         let mut p: Vec<RGB8> = Vec::with_capacity(720);
-        let s = 1.0f32; // saturation
-        let v = 1.0f32; // value
-        let c = v * s; // chroma
         for h in 0..720u32 { // hue
-            let hp = h as f32/60.0;
-            let x = c * (1.0-(hp % 2.0 - 1.0).abs());
-            let (r,g,b) = if 0.0 <= hp && hp < 1.0 {
-                (c, x, 0.0)
-            } else if 1.0 <= hp && hp < 2.0 {
-                (x, c, 0.0)
-            } else if 2.0 <= hp && hp < 3.0 {
-                (0.0, c, x)
-            } else if 3.0 <= hp && hp < 4.0 {
-                (0.0, x, c)
-            } else if 4.0 <= hp && hp < 5.0 {
-                (x, 0.0, c)
-            } else if 5.0 <= hp && hp < 6.0 {
-                (c, 0.0, x)
-            } else {
-                (0.0, 0.0, 0.0)
-            };
-            let m = v-c;
-            p.push((((r+m)*255.0) as u8, ((g+m)*255.0) as u8, ((b+m)*255.0) as u8));
+            p.push(hsv_to_rgb(h as f32, 1.0, 1.0));
         }
 
+        let prec = bignum::limbs_for_bits(GUARD_BITS);
+
         MandelEngine {
-            re0: -1.4f32,
-            re1:  0.6f32,
-            im0: -1.0f32,
-            im1:  1.0f32,
+            centre_re: BigFixed::from_f64(DEFAULT_CENTRE_RE, prec),
+            centre_im: BigFixed::from_f64(DEFAULT_CENTRE_IM, prec),
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_WIDTH,
+            fractal: Fractal::Mandelbrot,
+            exponent: 2.0,
+            variant: Variant::Classic,
+            expression: None,
+            formula: Arc::new(EscapeTime::new(&Fractal::Mandelbrot, 2.0, Variant::Classic).unwrap()),
+            custom_formula: false,
+            colouring: Colouring::Banded,
+            export_distance: false,
+            params: RenderParams::default(),
+            pool: tiles::Pool::new(tiles::default_threads()),
+            strategy: Strategy::BruteForce,
+            kept: None,
             buffer_width: w,
             buffer_height: h,
             palette: p
         }
     }
 
-    // Rescale pixel coord (x,y) into cspace
-    fn scale_coords(&self, x: u32, y: u32, w: u32, h: u32) -> (f32, f32) {
-        let x0 = self.re0;
-        let x1 = self.re1;
-        let y0 = self.im0;
-        let y1 = self.im1;
+    // Geometry of a w x h render of the current view
+    fn frame(&self, w: u32, h: u32) -> Frame {
+        Frame {
+            centre: Complex::new(self.centre_re.to_f64(), self.centre_im.to_f64()),
+            width: self.width,
+            height: self.height,
+            pixels_x: w,
+            pixels_y: h,
+            origin_x: 0,
+            origin_y: 0,
+            step_x: 1,
+            step_y: 1,
+            columns: w,
+            rows: h,
+            point: SamplePoint::Fixed(0.0, 0.0),
+        }
+    }
+
+    // Fractional limbs needed to place a pixel of the full size buffer
+    fn required_precision(&self) -> usize {
+        let span = if self.width < self.height { self.width } else { self.height };
+        let pixels = if self.buffer_width > self.buffer_height { self.buffer_width } else { self.buffer_height };
+        let pixel_size = span.abs() / (pixels as f64);
+        let bits = if pixel_size < 1.0 { (-pixel_size.log2()).ceil() as u32 } else { 0 };
+        bignum::limbs_for_bits(bits + GUARD_BITS)
+    }
+
+    // Grow the precision of the centre to keep up with zooming in
+    fn update_precision(&mut self) {
+        let prec = self.required_precision();
+        if prec > self.centre_re.precision() {
+            self.centre_re = self.centre_re.with_precision(prec);
+        }
+        if prec > self.centre_im.precision() {
+            self.centre_im = self.centre_im.with_precision(prec);
+        }
+    }
+
+    fn move_centre(&mut self, delta_r: f64, delta_i: f64) {
+        let dr = BigFixed::from_f64(delta_r, self.centre_re.precision());
+        let di = BigFixed::from_f64(delta_i, self.centre_im.precision());
+        self.centre_re = &self.centre_re + &dr;
+        self.centre_im = &self.centre_im + &di;
+    }
+
+    // Centre on decimal coordinates, parsed at the precision they were
+    // written with or that the width needs, whichever is greater
+    fn set_centre(&mut self, re: &str, im: &str, width: f64) -> bool {
+        let digits = if re.len() > im.len() { re.len() } else { im.len() };
+        let digit_bits = (digits as f64 * 10.0f64.log2()).ceil() as u32;
+
+        self.width = width;
+        self.height = width * (self.buffer_height as f64) / (self.buffer_width as f64);
+
+        let required = self.required_precision();
+        let given = bignum::limbs_for_bits(digit_bits + GUARD_BITS);
+        let prec = if required > given { required } else { given };
+
+        match (BigFixed::parse(re, prec), BigFixed::parse(im, prec)) {
+            (Some(re), Some(im)) => {
+                self.centre_re = re;
+                self.centre_im = im;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // Render with a formula of the caller's own, until the next
+    // SetFractal, SetExponent, SetVariant or SetFormula command
+    pub fn set_formula(&mut self, formula: Box<dyn FractalFormula>) {
+        self.formula = Arc::from(formula);
+        self.custom_formula = true;
+    }
+
+    // Rebuild the formula from the current settings
+    fn update_formula(&mut self) {
+        match self.expression {
+            Some(ref expression) => {
+                if let Some(formula) = ExpressionFormula::new(expression, &self.fractal) {
+                    self.formula = Arc::new(formula);
+                }
+            },
+            None => {
+                if let Some(formula) = EscapeTime::new(&self.fractal, self.exponent, self.variant) {
+                    self.formula = Arc::new(formula);
+                }
+            },
+        }
+        self.custom_formula = false;
+    }
+
+    // Deep zooms iterate every pixel relative to precise reference orbits,
+    // which is only worked out for the classic Mandelbrot set
+    fn is_deep(&self, frame: &Frame) -> bool {
+        match self.fractal {
+            Fractal::Mandelbrot => frame.pixel_size() < DEEP_ZOOM_PIXEL_SIZE && self.is_classic(),
+            _ => false,
+        }
+    }
+
+    // Whether the built in z^2 + c is in use
+    fn is_classic(&self) -> bool {
+        self.exponent == 2.0 && self.variant == Variant::Classic &&
+            self.expression.is_none() && !self.custom_formula
+    }
+
+    // Whether the derivative needs tracking for distance estimates
+    fn wants_distance(&self) -> bool {
+        match self.colouring {
+            Colouring::Distance { .. } => true,
+            _ => self.export_distance,
+        }
+    }
+
+    // Whether the formula can provide the derivative at all
+    fn has_derivative(&self) -> bool {
+        self.formula.derivative(Complex::zero(), self.formula.init_derivative()).is_some()
+    }
+
+    // Iteration limit for the current view
+    fn max_iteration(&self) -> u32 {
+        let decades = (DEFAULT_WIDTH / self.width.abs()).log10();
+        if self.params.auto_iterations && decades > 0.0 {
+            let extra = (decades * AUTO_ITERATIONS_PER_DECADE).min((u32::MAX - self.params.max_iteration) as f64);
+            self.params.max_iteration + extra as u32
+        } else {
+            self.params.max_iteration
+        }
+    }
+
+    // Radius beyond which an orbit has escaped
+    fn bailout(&self) -> f64 {
+        let radius = match self.colouring {
+            Colouring::Banded => self.params.bailout,
+            Colouring::Smooth { bailout } => bailout.max(self.params.bailout),
+            Colouring::Distance { .. } => DISTANCE_BAILOUT.max(self.params.bailout),
+        };
+        if self.wants_distance() { radius.max(DISTANCE_BAILOUT) } else { radius }
+    }
 
-        let xx = (x as f32) / (w as f32) * (x1-x0) + x0;
-        let yy = (y as f32) / (h as f32) * (y1-y0) + y0;
+    // Palette colour for a pixel, stepping through the entries with each
+    // iteration, or gliding between them with the fractional escape count.
+    // Distance colouring draws the boundary in black on white instead.
+    fn colour(&self, sample: Sample, max_iteration: u32, pixel_size: f64) -> RGB8 {
+        let degree = self.formula.degree().abs();
+        let escaped = sample.iteration < max_iteration;
+        match (self.colouring, sample.distance) {
+            (Colouring::Smooth { .. }, _) if escaped && degree > 1.0 => {
+                // n + 1 - log_d(ln |z|), continuous across iterations
+                let log_z = 0.5 * sample.norm_sqr.ln();
+                let mu = sample.iteration as f64 + 1.0 - log_z.ln() / degree.ln();
+                let mu = mu.max(0.0) % PALETTE_CYCLE as f64;
 
-        (xx, yy)
+                let i = mu.floor() as usize;
+                let t = mu - i as f64;
+                let (r0, g0, b0) = self.palette[i];
+                let (r1, g1, b1) = self.palette[(i + 1) % PALETTE_CYCLE];
+                let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+                (mix(r0, r1), mix(g0, g1), mix(b0, b1))
+            },
+            (Colouring::Distance { thickness }, Some(distance)) => {
+                // Fade out to white over `thickness` pixels from the set
+                let v = (distance / (thickness * pixel_size)).min(1.0);
+                let grey = (v * 255.0) as u8;
+                (grey, grey, grey)
+            },
+            _ => self.palette[sample.iteration as usize % PALETTE_CYCLE],
+        }
     }
 
     pub fn serve(&mut self, cmd_chan: &Receiver<EngineCommand>, progress_chan: &Sender<EngineStatus>) {
         let mut running = true;
+        let mut pending = VecDeque::new();
+        let mut generation = 0;
         while running {
-            // pan/zoom by 10% of width
-            let delta_r = ((self.re1 - self.re0)*0.1f32).abs();
-            let delta_i = ((self.im1 - self.im0)*0.1f32).abs();
+            // pan/zoom by about 10% of width, in whole pixels of both render
+            // sizes so that a pan can reuse the last frame
+            let delta_r = pan_step(self.buffer_width, PREVIEW_WIDTH as u32) * (self.width / self.buffer_width as f64).abs();
+            let delta_i = pan_step(self.buffer_height, PREVIEW_HEIGHT as u32) * (self.height / self.buffer_height as f64).abs();
             println!("delta r,i {},{}", delta_r, delta_i);
 
-            let cmd = match cmd_chan.recv() {
-                Ok(cmd) => cmd,
-                Err(e) => {
-                    println!("serve: cmd_chan error: {}", e);
-                    running = false;
-                    break;
-                }
+            // Take in everything queued up so far, so that a burst of
+            // navigation is applied back to back and only the view it ends
+            // on gets rendered
+            while let Ok(cmd) = cmd_chan.try_recv() {
+                pending.push_back(cmd);
+            }
+
+            // Commands that came in during the last render go first
+            let cmd = match pending.pop_front() {
+                Some(cmd) => cmd,
+                None => match cmd_chan.recv() {
+                    Ok(cmd) => cmd,
+                    Err(e) => {
+                        println!("serve: cmd_chan error: {}", e);
+                        break;
+                    }
+                },
             };
 
             println!("engine: command {:?}", cmd);
+
+            // Moving the view leaves the kept samples good for reuse, but
+            // anything else may change what every pixel comes out as
+            match cmd {
+                EngineCommand::PanLeft | EngineCommand::PanRight |
+                EngineCommand::PanUp | EngineCommand::PanDown |
+                EngineCommand::ZoomIn | EngineCommand::ZoomOut |
+                EngineCommand::UpdateRegion(..) | EngineCommand::SetCentre(..) |
+                EngineCommand::Render(_) | EngineCommand::SetThreads(_) => (),
+                _ => self.kept = None,
+            }
+
             match cmd {
                 EngineCommand::UpdateRegion(re0, re1, im0, im1) => {
-                    self.re0 = re0; self.re1 = re1; self.im0 = im0; self.im1 = im1;
+                    let prec = self.centre_re.precision();
+                    self.centre_re = BigFixed::from_f64((re0 + re1)*0.5, prec);
+                    self.centre_im = BigFixed::from_f64((im0 + im1)*0.5, prec);
+                    self.width = re1 - re0;
+                    self.height = im1 - im0;
+                    self.update_precision();
+                },
+                EngineCommand::SetCentre(re, im, width) => {
+                    if !self.set_centre(&re, &im, width) {
+                        println!("engine: invalid centre {}, {}", re, im);
+                        let bad = if BigFixed::parse(&re, 1).is_none() { re } else { im };
+                        progress_chan.send(EngineStatus::Error(EngineError::InvalidCoordinate(bad))).unwrap();
+                    }
                 },
                 EngineCommand::ZoomIn => {
-                    self.re0 += delta_r;
-                    self.re1 -= delta_r;
-                    self.im0 += delta_i;
-                    self.im1 -= delta_i;
+                    self.width -= 2.0*delta_r;
+                    self.height -= 2.0*delta_i;
+                    self.update_precision();
                 },
                 EngineCommand::ZoomOut => {
-                    self.re0 -= delta_r;
-                    self.re1 += delta_r;
-                    self.im0 -= delta_i;
-                    self.im1 += delta_i;
+                    self.width += 2.0*delta_r;
+                    self.height += 2.0*delta_i;
                 },
-                EngineCommand::PanLeft => {
-                    self.re0 -= delta_r;
-                    self.re1 -= delta_r;
+                EngineCommand::PanLeft => self.move_centre(-delta_r, 0.0),
+                EngineCommand::PanRight => self.move_centre(delta_r, 0.0),
+                EngineCommand::PanUp => self.move_centre(0.0, delta_i),
+                EngineCommand::PanDown => self.move_centre(0.0, -delta_i),
+                EngineCommand::SetFractal(fractal) => {
+                    let valid = match fractal {
+                        Fractal::Newton { ref coefficients } => Polynomial::new(coefficients).degree() > 0,
+                        _ => true,
+                    };
+                    if valid {
+                        self.fractal = fractal;
+                        self.update_formula();
+                    } else {
+                        println!("engine: Newton fractal needs a polynomial of degree 1 or more");
+                        progress_chan.send(EngineStatus::Error(EngineError::InvalidPolynomial)).unwrap();
+                    }
                 },
-                EngineCommand::PanRight => {
-                    self.re0 += delta_r;
-                    self.re1 += delta_r;
+                EngineCommand::SetExponent(d) => {
+                    self.exponent = d;
+                    self.update_formula();
                 },
-                EngineCommand::PanUp => {
-                    self.im0 += delta_i;
-                    self.im1 += delta_i;
+                EngineCommand::SetVariant(variant) => {
+                    self.variant = variant;
+                    self.update_formula();
                 },
-                EngineCommand::PanDown => {
-                    self.im0 -= delta_i;
-                    self.im1 -= delta_i;
+                EngineCommand::SetFormula(source) => {
+                    if source.trim().is_empty() {
+                        self.expression = None;
+                        self.update_formula();
+                    } else {
+                        match Expression::parse(&source) {
+                            Ok(expression) => {
+                                self.expression = Some(expression);
+                                self.update_formula();
+                            },
+                            Err(e) => {
+                                println!("engine: formula error at {}", e);
+                                progress_chan.send(EngineStatus::Error(EngineError::Formula(e))).unwrap();
+                            },
+                        }
+                    }
+                },
+                EngineCommand::SetColouring(colouring) => self.colouring = colouring,
+                EngineCommand::ExportDistance(export) => self.export_distance = export,
+                EngineCommand::SetRenderParams(params) => self.params = params,
+                EngineCommand::SetThreads(threads) => {
+                    let threads = if threads > 0 { threads } else { tiles::default_threads() };
+                    if threads != self.pool.size() {
+                        self.pool = tiles::Pool::new(threads);
+                    }
+                },
+                EngineCommand::SetStrategy(strategy) => self.strategy = strategy,
+                EngineCommand::Render(typ) => {
+                    generation += 1;
+                    if pending.iter().any(job::supersedes) {
+                        println!("engine: render {} superseded", generation);
+                        progress_chan.send(EngineStatus::Cancelled(generation)).unwrap();
+                    } else {
+                        let job = Job::new(generation, cmd_chan, progress_chan, pending);
+                        if self.process(typ, &job).is_err() {
+                            println!("engine: render {} cancelled", generation);
+                            job.send(EngineStatus::Cancelled(generation));
+                        }
+                        pending = job.into_pending();
+                    }
                 },
-                EngineCommand::Render(typ) => self.process(typ, progress_chan),
                 EngineCommand::Shutdown => running = false,
             }
         }
@@ -145,57 +967,1032 @@ impl MandelEngine {
     }
 
     // Evalute entire region
-    fn process(&mut self, typ: RenderType, progress_chan: &Sender<EngineStatus>) {
+    fn process(&mut self, typ: RenderType, job: &Job) -> Result<(), Cancelled> {
 
         let (width, height) = match typ {
             RenderType::PreviewRender => (PREVIEW_WIDTH as u32, PREVIEW_HEIGHT as u32),
-            RenderType::FullRender => (self.buffer_width, self.buffer_height),
+            RenderType::FullRender | RenderType::ProgressiveRender => (self.buffer_width, self.buffer_height),
         };
 
         let mut img: Vec<u8> = Vec::with_capacity((width*height*3) as usize);
 
-        let max_iteration = 500;
+        let max_iteration = self.max_iteration();
 
         println!("+++ process {}x{} RGB8 in {} bytes", width, height, img.capacity());
-        println!("            re: {}..{} im: {}..{}", self.re0, self.re1, self.im0, self.im1);
+        println!("            centre: {}, {} size: {}x{}", self.centre_re, self.centre_im, self.width, self.height);
 
-        progress_chan.send(EngineStatus::Startup).unwrap();
+        // Don't start on a render that is already out of date
+        try!(job.check());
+        job.send(EngineStatus::Startup);
 
-        // Process each pixel
-        for py in 0..height {
-            for px in 0..width {
+        let frame = self.frame(width, height);
 
-                // Project pixels into Mandelbrot domain
-                let (x0, y0) = self.scale_coords(px, py, width, height);
+        // After a pan, most of the frame is already done, so render the
+        // rest at full resolution in one go
+        let progressive = match typ {
+            RenderType::ProgressiveRender => self.pan_offset(&frame, max_iteration).is_none(),
+            _ => false,
+        };
+        let passes = if progressive { PROGRESSIVE_PASSES.len() as u32 } else { 1 };
 
-                let mut x = 0.0f32;
-                let mut y = 0.0f32;
-                let mut iteration = 0;
+        // After a zoom, show the last frame rescaled while the new one is
+        // worked out
+        let rescale = match typ {
+            RenderType::PreviewRender => false,
+            _ => self.pan_offset(&frame, max_iteration).is_none(),
+        };
+        if rescale {
+            if let Some(lines) = self.zoom_lines(&frame) {
+                let preview = self.zoom_preview(&frame, max_iteration, &lines);
+                job.send(EngineStatus::PartialFrame(job.generation(), 0, passes, rgb_bytes(preview)));
+                let rescaled = try!(self.zoom_frame(&frame, max_iteration, &lines, job));
+                job.send(EngineStatus::PartialFrame(job.generation(), 0, passes, rgb_bytes(rescaled)));
+            }
+        }
 
-                // Iterate!
-                while (x*x + y*y < 4.0) && (iteration < max_iteration) {
-                    let x1 = x*x - y*y + x0;
-                    y = 2.0*x*y + y0;
-                    x = x1;
+        match typ {
+            RenderType::ProgressiveRender if progressive => {
+                img.resize((width*height*3) as usize, 0);
+                for (pass, &(x0, y0, dx, dy, bw, bh)) in PROGRESSIVE_PASSES.iter().enumerate() {
+                    let grid = frame.subgrid(x0, y0, dx, dy);
+                    if grid.columns == 0 || grid.rows == 0 {
+                        continue;
+                    }
+                    let colours = try!(self.render_colours(&grid, max_iteration, false, job));
 
-                    iteration += 1;
+                    // Each new pixel covers its block until the later
+                    // passes fill in the rest
+                    for (i, &(r, g, b)) in colours.iter().enumerate() {
+                        let (px, py) = grid.pixel(i as u32 % grid.columns, i as u32 / grid.columns);
+                        for y in py..(py + bh).min(height) {
+                            for x in px..(px + bw).min(width) {
+                                let k = ((y*width + x) * 3) as usize;
+                                img[k] = r;
+                                img[k+1] = g;
+                                img[k+2] = b;
+                            }
+                        }
+                    }
+                    if pass + 1 < passes as usize {
+                        job.send(EngineStatus::PartialFrame(job.generation(), pass as u32 + 1, passes, img.clone()));
+                    }
+                }
+            },
+            RenderType::FullRender if self.params.supersampling != Supersampling::None => {
+                let colours = if self.params.adaptive {
+                    try!(self.supersample_edges(&frame, max_iteration, job))
+                } else {
+                    try!(self.supersample(&frame, max_iteration, job))
+                };
+                for (r, g, b) in colours {
+                    img.push(r);
+                    img.push(g);
+                    img.push(b);
+                }
+            },
+            _ => {
+                let colours = try!(self.render_colours(&frame, max_iteration, self.export_distance, job));
+
+                // Plot
+                for (r, g, b) in colours {
+                    img.push(r);
+                    img.push(g);
+                    img.push(b);
+                }
+            },
+        }
+
+        job.send(EngineStatus::RenderComplete(job.generation(), typ, img));
+        Ok(())
+    }
+
+    // Colour of each pixel, averaged in linear light over the samples the
+    // render parameters ask for.  Any distance field comes from the first
+    // sample in each pixel.
+    fn supersample(&mut self, frame: &Frame, max_iteration: u32, job: &Job) -> Result<Vec<RGB8>, Cancelled> {
+        let points = sample_points(self.params.supersampling);
+        println!("            supersampling with {} samples per pixel", points.len());
+
+        let mut sums = vec![(0.0, 0.0, 0.0); (frame.columns*frame.rows) as usize];
+        for (k, &point) in points.iter().enumerate() {
+            let export = k == 0 && self.export_distance;
+            let colours = try!(self.render_colours(&frame.sampled_at(point), max_iteration, export, job));
+            for (sum, (r, g, b)) in sums.iter_mut().zip(colours) {
+                sum.0 += srgb_to_linear(r);
+                sum.1 += srgb_to_linear(g);
+                sum.2 += srgb_to_linear(b);
+            }
+        }
+
+        let n = points.len() as f64;
+        Ok(sums.into_iter().map(|(r, g, b)| (linear_to_srgb(r / n), linear_to_srgb(g / n), linear_to_srgb(b / n))).collect())
+    }
+
+    // As supersample, but only for pixels that stand out from their
+    // neighbours after a plain render, going by their colours and the
+    // iteration counts it kept.  Deep zooms have no way to iterate
+    // scattered pixels, so they get every pixel supersampled.
+    fn supersample_edges(&mut self, frame: &Frame, max_iteration: u32, job: &Job) -> Result<Vec<RGB8>, Cancelled> {
+        if self.is_deep(frame) {
+            return self.supersample(frame, max_iteration, job);
+        }
+
+        let export = self.export_distance;
+        let mut colours = try!(self.render_colours(frame, max_iteration, export, job));
+        let iterations: Option<Vec<u32>> = match self.pan_offset(frame, max_iteration) {
+            Some((0, 0)) => self.kept.as_ref().map(|kept| kept.samples.iter().map(|s| s.unwrap().iteration).collect()),
+            _ => None,
+        };
+
+        // Mark both pixels of every neighbouring pair that differ strongly
+        let (width, height) = (frame.columns as i64, frame.rows as i64);
+        let differ = |a: usize, b: usize| {
+            let (ca, cb) = (colours[a], colours[b]);
+            let step = |p: u8, q: u8| (p as i32 - q as i32).abs();
+            if step(ca.0, cb.0).max(step(ca.1, cb.1)).max(step(ca.2, cb.2)) > ADAPTIVE_COLOUR_STEP {
+                return true;
+            }
+            match iterations {
+                Some(ref n) => (n[a] == max_iteration) != (n[b] == max_iteration) ||
+                    (n[a] as i64 - n[b] as i64).abs() > ADAPTIVE_ITERATION_STEP,
+                None => false,
+            }
+        };
+        let mut edge = vec![false; (width*height) as usize];
+        for y in 0..height {
+            for x in 0..width {
+                let a = (y*width + x) as usize;
+                for &(dx, dy) in [(1, 0), (-1, 1), (0, 1), (1, 1)].iter() {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || nx >= width || ny >= height {
+                        continue;
+                    }
+                    let b = (ny*width + nx) as usize;
+                    if differ(a, b) {
+                        edge[a] = true;
+                        edge[b] = true;
+                    }
                 }
+            }
+        }
+        let pixels: Vec<(u32, u32)> = (0..width*height).filter(|&i| edge[i as usize])
+            .map(|i| ((i % width) as u32, (i / width) as u32)).collect();
 
-                // Colour and plot
-                let color = self.palette.get(iteration % 580);
-                let &(r,g, b) = color.unwrap();
+        let points = sample_points(self.params.supersampling);
+        println!("            supersampling {} edge pixels of {} with {} samples each",
+                 pixels.len(), width*height, points.len());
 
-                img.push(r);
-                img.push(g);
-                img.push(b);
+        let pixels = Arc::new(pixels);
+        let mut sums = vec![(0.0, 0.0, 0.0); pixels.len()];
+        for &point in points.iter() {
+            let samples = try!(self.render_pixels(&frame.sampled_at(point), pixels.clone(), max_iteration, job));
+            for (sum, (r, g, b)) in sums.iter_mut().zip(samples) {
+                sum.0 += srgb_to_linear(r);
+                sum.1 += srgb_to_linear(g);
+                sum.2 += srgb_to_linear(b);
             }
-            if py % 100 == 0 {
-                progress_chan.send(EngineStatus::Processing(py)).unwrap();
+        }
+
+        let n = points.len() as f64;
+        for (&(x, y), (r, g, b)) in pixels.iter().zip(sums) {
+            colours[(y*frame.columns + x) as usize] = (linear_to_srgb(r / n), linear_to_srgb(g / n), linear_to_srgb(b / n));
+        }
+        Ok(colours)
+    }
+
+    // Colours of just the listed pixels of the frame's grid
+    fn render_pixels(&self, frame: &Frame, pixels: Arc<Vec<(u32, u32)>>, max_iteration: u32,
+                     job: &Job) -> Result<Vec<RGB8>, Cancelled> {
+        match self.fractal {
+            Fractal::Newton { ref coefficients } if !self.custom_formula => {
+                let poly = Polynomial::new(coefficients);
+                let roots = poly.roots();
+                let frame = *frame;
+                tiles::render(pixels.len() as u32, 1, &self.pool, move |i, _| {
+                    let (px, py) = pixels[i as usize];
+                    newton_colour(&poly, &roots, frame.centre + frame.scale_coords(px, py), max_iteration)
+                }, job)
+            },
+            _ => {
+                let pixel_size = frame.pixel_size();
+                let samples = try!(self.iterate_list(frame, pixels, max_iteration, job));
+                Ok(samples.iter().map(|&(sample, _)| self.colour(sample, max_iteration, pixel_size)).collect())
+            },
+        }
+    }
+
+    // Colour of each pixel in the frame's grid, sending the distance field
+    // too if `export` is set
+    fn render_colours(&mut self, frame: &Frame, max_iteration: u32, export: bool,
+                      job: &Job) -> Result<Vec<RGB8>, Cancelled> {
+
+        let pixel_size = frame.pixel_size();
+        match self.fractal {
+            Fractal::Newton { ref coefficients } if !self.custom_formula => {
+                self.render_newton(coefficients, frame, max_iteration, job)
+            },
+            _ => {
+                let distance = self.wants_distance() && self.has_derivative();
+                if self.wants_distance() && !distance {
+                    println!("engine: formula has no derivative, so no distance estimate");
+                    job.send(EngineStatus::Error(EngineError::NoDerivative));
+                }
+
+                let (samples, saved) = match self.pan_offset(frame, max_iteration) {
+                    Some((dx, dy)) if frame.is_whole() && frame.is_plain() => try!(self.pan(frame, max_iteration, dx, dy, job)),
+                    _ => match try!(self.complete(frame, max_iteration, job)) {
+                        Some(result) => result,
+                        None => try!(self.iterate_samples(frame, max_iteration, job)),
+                    },
+                };
+                if frame.is_plain() {
+                    self.keep(frame, max_iteration, &samples);
+                }
+
+                let total = samples.iter().fold(0u64, |n, s| n + s.iteration as u64);
+                let stats = RenderStats { iterations: total - saved, iterations_saved: saved };
+                println!("            {} iterations, {} saved", stats.iterations, stats.iterations_saved);
+                job.send(EngineStatus::Statistics(job.generation(), stats));
+
+                // Distances go out in pixels, alongside the image
+                if export && distance {
+                    let field = samples.iter()
+                        .map(|s| (s.distance.unwrap_or(0.0) / pixel_size) as f32)
+                        .collect();
+                    job.send(EngineStatus::DistanceField(job.generation(), field));
+                }
+
+                Ok(samples.iter().map(|&sample| self.colour(sample, max_iteration, pixel_size)).collect())
+            },
+        }
+    }
+
+    // Escape of each pixel in the frame's grid, and the number of iterations
+    // skipped
+    fn iterate_samples(&self, frame: &Frame, max_iteration: u32,
+                       job: &Job) -> Result<(Vec<Sample>, u64), Cancelled> {
+
+        if self.is_deep(frame) {
+            Ok((try!(self.iterate_perturbed(frame, max_iteration, job)), 0))
+        } else {
+            self.iterate_direct(frame, max_iteration, job)
+        }
+    }
+
+    // Escape of each pixel in the frame's grid, taking those that the kept
+    // samples of this same view already have, such as the lines worked out
+    // after a zoom, and iterating the rest.  Samples taken count as saved.
+    // None if there are none to take.
+    fn complete(&self, frame: &Frame, max_iteration: u32,
+                job: &Job) -> Result<Option<(Vec<Sample>, u64)>, Cancelled> {
+        if !frame.is_plain() || self.is_deep(frame) || !self.is_kept_view(frame, max_iteration) {
+            return Ok(None);
+        }
+
+        let mut samples = Vec::with_capacity((frame.columns*frame.rows) as usize);
+        let mut missing = Vec::new();
+        let mut saved = 0;
+        {
+            let kept = self.kept.as_ref().unwrap();
+            for y in 0..frame.rows {
+                for x in 0..frame.columns {
+                    let (px, py) = frame.pixel(x, y);
+                    let sample = kept.samples[(py*frame.pixels_x + px) as usize];
+                    match sample {
+                        Some(sample) => saved += sample.iteration as u64,
+                        None => missing.push((x, y)),
+                    }
+                    samples.push(sample);
+                }
             }
         }
+        if missing.len() == samples.len() {
+            return Ok(None);
+        }
+
+        let missing = Arc::new(missing);
+        let computed = try!(self.iterate_list(frame, missing.clone(), max_iteration, job));
+        for (&(x, y), (sample, skipped)) in missing.iter().zip(computed) {
+            samples[(y*frame.columns + x) as usize] = Some(sample);
+            saved += skipped;
+        }
+        Ok(Some((samples.into_iter().map(|s| s.unwrap()).collect(), saved)))
+    }
+
+    // Whole pixels (dx, dy) that the kept samples would have to move by to
+    // line up with the frame, if they are all there and still in view
+    fn pan_offset(&self, frame: &Frame, max_iteration: u32) -> Option<(i64, i64)> {
+        let kept = match self.kept {
+            Some(ref kept) => kept,
+            None => return None,
+        };
+        if kept.max_iteration != max_iteration || kept.frame.width != frame.width ||
+            kept.frame.height != frame.height || kept.frame.pixels_x != frame.pixels_x ||
+            kept.frame.pixels_y != frame.pixels_y || kept.samples.iter().any(|s| s.is_none()) {
+            return None;
+        }
 
-        progress_chan.send(EngineStatus::RenderComplete(typ, img)).unwrap();
+        let dx = (&self.centre_re - &kept.centre_re).to_f64() / (frame.width / frame.pixels_x as f64);
+        let dy = (&self.centre_im - &kept.centre_im).to_f64() / (frame.height / frame.pixels_y as f64);
+        if (dx - dx.round()).abs() > PAN_TOLERANCE || (dy - dy.round()).abs() > PAN_TOLERANCE {
+            return None;
+        }
+        let (dx, dy) = (dx.round() as i64, dy.round() as i64);
+        if dx.abs() >= frame.pixels_x as i64 || dy.abs() >= frame.pixels_y as i64 {
+            return None;
+        }
+        Some((dx, dy))
+    }
+
+    // Shift the kept samples across by (dx, dy) pixels, and iterate just
+    // the strips that come into view.  Samples carried over count as saved.
+    fn pan(&self, frame: &Frame, max_iteration: u32, dx: i64, dy: i64,
+           job: &Job) -> Result<(Vec<Sample>, u64), Cancelled> {
+
+        let (width, height) = (frame.columns as i64, frame.rows as i64);
+        let mut samples: Vec<Option<Sample>> = vec![None; (width*height) as usize];
+        let mut saved = 0;
+        {
+            let kept = self.kept.as_ref().unwrap();
+            for y in 0..height {
+                for x in 0..width {
+                    let (ox, oy) = (x + dx, y + dy);
+                    if ox >= 0 && ox < width && oy >= 0 && oy < height {
+                        let sample = kept.samples[(oy*width + ox) as usize];
+                        samples[(y*width + x) as usize] = sample;
+                        saved += sample.unwrap().iteration as u64;
+                    }
+                }
+            }
+        }
+        println!("            pan by {},{}: reusing {} of {} pixels",
+                 dx, dy, (width - dx.abs())*(height - dy.abs()), width*height);
+
+        // Full rows along the top or bottom, then the columns beside them
+        let (y0, y1) = if dy > 0 { (0, height - dy) } else { (-dy, height) };
+        let mut windows = Vec::with_capacity(2);
+        if dy > 0 {
+            windows.push(frame.window(0, (height - dy) as u32, width as u32, dy as u32));
+        } else if dy < 0 {
+            windows.push(frame.window(0, 0, width as u32, -dy as u32));
+        }
+        if dx > 0 {
+            windows.push(frame.window((width - dx) as u32, y0 as u32, dx as u32, (y1 - y0) as u32));
+        } else if dx < 0 {
+            windows.push(frame.window(0, y0 as u32, -dx as u32, (y1 - y0) as u32));
+        }
+
+        for window in windows {
+            if window.columns == 0 || window.rows == 0 {
+                continue;
+            }
+            let (strip, strip_saved) = try!(self.iterate_samples(&window, max_iteration, job));
+            for (i, sample) in strip.into_iter().enumerate() {
+                let (px, py) = window.pixel(i as u32 % window.columns, i as u32 / window.columns);
+                samples[(py*frame.pixels_x + px) as usize] = Some(sample);
+            }
+            saved += strip_saved;
+        }
+
+        Ok((samples.into_iter().map(|s| s.unwrap()).collect(), saved))
+    }
+
+    // Whether the kept samples are of the current view
+    fn is_kept_view(&self, frame: &Frame, max_iteration: u32) -> bool {
+        match self.kept {
+            Some(ref kept) => kept.centre_re == self.centre_re && kept.centre_im == self.centre_im &&
+                kept.max_iteration == max_iteration && kept.frame.width == frame.width &&
+                kept.frame.height == frame.height && kept.frame.pixels_x == frame.pixels_x &&
+                kept.frame.pixels_y == frame.pixels_y,
+            None => false,
+        }
+    }
+
+    // Store samples of the current view for later pans, starting afresh if
+    // the view has changed since the last ones
+    fn keep(&mut self, frame: &Frame, max_iteration: u32, samples: &[Sample]) {
+        let pixels: Vec<(u32, u32)> = (0..frame.columns*frame.rows)
+            .map(|i| frame.pixel(i % frame.columns, i / frame.columns)).collect();
+        self.keep_pixels(frame, max_iteration, &pixels, samples);
+    }
+
+    // As keep, for the samples of just the listed image pixels
+    fn keep_pixels(&mut self, frame: &Frame, max_iteration: u32, pixels: &[(u32, u32)], samples: &[Sample]) {
+        if !self.is_kept_view(frame, max_iteration) {
+            self.kept = Some(Kept {
+                centre_re: self.centre_re.clone(),
+                centre_im: self.centre_im.clone(),
+                frame: *frame,
+                max_iteration: max_iteration,
+                samples: vec![None; (frame.pixels_x*frame.pixels_y) as usize],
+            });
+        }
+
+        let kept = self.kept.as_mut().unwrap();
+        for (&(px, py), &sample) in pixels.iter().zip(samples) {
+            kept.samples[(py*frame.pixels_x + px) as usize] = Some(sample);
+        }
+    }
+
+    // Settings for iterating pixels directly
+    fn escape(&self, max_iteration: u32) -> Escape {
+        let track = self.wants_distance();
+        Escape {
+            formula: self.formula.clone(),
+            bailout: self.bailout(),
+            track: track,
+            bulbs: match self.fractal {
+                Fractal::Mandelbrot => self.is_classic(),
+                _ => false,
+            },
+            vector: self.is_classic() && !track && simd::available(),
+            subdivide: self.strategy == Strategy::Subdivide,
+            fill_escaped: self.colouring == Colouring::Banded && !track,
+            max_iteration: max_iteration,
+        }
+    }
+
+    // Where the rows and columns of a zoomed frame fall among those of the
+    // kept samples, in the manner of XaoS: each old line that lands close
+    // enough to a new one stands in for it.  None if there is too little
+    // to reuse.
+    fn zoom_lines(&self, frame: &Frame) -> Option<ZoomLines> {
+        let kept = match self.kept {
+            Some(ref kept) => kept,
+            None => return None,
+        };
+        if !frame.is_whole() || kept.frame.pixels_x != frame.pixels_x || kept.frame.pixels_y != frame.pixels_y ||
+            frame.pixel_size() < DEEP_ZOOM_PIXEL_SIZE || kept.samples.iter().any(|s| s.is_none()) {
+            return None;
+        }
+
+        // Where the new lines fall among the old ones
+        let (pixels_x, pixels_y) = (frame.pixels_x, frame.pixels_y);
+        let scale_x = frame.width / kept.frame.width;
+        let scale_y = frame.height / kept.frame.height;
+        let offset_x = ((&self.centre_re - &kept.centre_re).to_f64() / kept.frame.width + 0.5 - 0.5*scale_x) * pixels_x as f64;
+        let offset_y = ((&self.centre_im - &kept.centre_im).to_f64() / kept.frame.height + 0.5 - 0.5*scale_y) * pixels_y as f64;
+        let columns = nearest_lines(pixels_x, offset_x, scale_x);
+        let rows = nearest_lines(pixels_y, offset_y, scale_y);
+
+        let reused = |lines: &[Option<u32>]| lines.iter().filter(|line| line.is_some()).count();
+        if reused(&columns) * 2 < pixels_x as usize || reused(&rows) * 2 < pixels_y as usize {
+            return None;
+        }
+        println!("            zoom: reusing {} columns and {} rows", reused(&columns), reused(&rows));
+        Some((columns, rows))
+    }
+
+    // The zoomed frame straight away, with each line in between the reused
+    // ones copied from the nearest of them
+    fn zoom_preview(&self, frame: &Frame, max_iteration: u32,
+                    lines: &ZoomLines) -> Vec<RGB8> {
+        let kept = self.kept.as_ref().unwrap();
+        let columns = fill_lines(&lines.0);
+        let rows = fill_lines(&lines.1);
+
+        let pixel_size = frame.pixel_size();
+        let mut colours = Vec::with_capacity((frame.pixels_x*frame.pixels_y) as usize);
+        for y in 0..frame.pixels_y {
+            for x in 0..frame.pixels_x {
+                let sample = kept.reused(columns[x as usize], rows[y as usize], max_iteration);
+                colours.push(self.colour(sample, max_iteration, pixel_size));
+            }
+        }
+        colours
+    }
+
+    // The zoomed frame with the lines in between the reused ones worked
+    // out.  Those are kept, so the render that follows only has to work out
+    // the reused lines afresh.
+    fn zoom_frame(&mut self, frame: &Frame, max_iteration: u32, lines: &ZoomLines,
+                  job: &Job) -> Result<Vec<RGB8>, Cancelled> {
+        let (ref columns, ref rows) = *lines;
+        let (pixels_x, pixels_y) = (frame.pixels_x, frame.pixels_y);
+
+        let mut missing = Vec::new();
+        for y in 0..pixels_y {
+            for x in 0..pixels_x {
+                if columns[x as usize].is_none() || rows[y as usize].is_none() {
+                    missing.push((x, y));
+                }
+            }
+        }
+
+        let missing = Arc::new(missing);
+        let computed = try!(self.iterate_list(frame, missing.clone(), max_iteration, job));
+
+        let total = computed.iter().fold(0u64, |n, &(s, _)| n + s.iteration as u64);
+        let saved = computed.iter().fold(0u64, |n, &(_, saved)| n + saved);
+        job.send(EngineStatus::Statistics(job.generation(), RenderStats { iterations: total - saved, iterations_saved: saved }));
+
+        let mut samples = Vec::with_capacity((pixels_x*pixels_y) as usize);
+        {
+            let kept = self.kept.as_ref().unwrap();
+            for y in 0..pixels_y {
+                for x in 0..pixels_x {
+                    samples.push(kept.reused(columns[x as usize].unwrap_or(0), rows[y as usize].unwrap_or(0), max_iteration));
+                }
+            }
+        }
+        for (&(x, y), &(sample, _)) in missing.iter().zip(computed.iter()) {
+            samples[(y*pixels_x + x) as usize] = sample;
+        }
+
+        let fresh: Vec<Sample> = computed.into_iter().map(|(sample, _)| sample).collect();
+        self.keep_pixels(frame, max_iteration, &missing, &fresh);
+
+        let pixel_size = frame.pixel_size();
+        Ok(samples.iter().map(|&sample| self.colour(sample, max_iteration, pixel_size)).collect())
+    }
+
+    // Escape of just the listed pixels of the frame's grid, computed in
+    // plain f64, with the iterations skipped for each
+    fn iterate_list(&self, frame: &Frame, pixels: Arc<Vec<(u32, u32)>>, max_iteration: u32,
+                    job: &Job) -> Result<Vec<(Sample, u64)>, Cancelled> {
+        let frame = *frame;
+        let escape = self.escape(max_iteration);
+        tiles::render_blocks(pixels.len() as u32, 1, &self.pool, move |x0, _, x1, _, values: &mut Vec<(Sample, u64)>| {
+            escape.iterate_pixels(&frame, &pixels[x0 as usize..x1 as usize], values)
+        }, job)
+    }
+
+    // Escape of each pixel, computed in plain f64, and the number of
+    // iterations skipped by spotting interior points early
+    fn iterate_direct(&self, frame: &Frame, max_iteration: u32,
+                      job: &Job) -> Result<(Vec<Sample>, u64), Cancelled> {
+
+        let frame = *frame;
+        let escape = self.escape(max_iteration);
+
+        // Project pixels into the formula's domain
+        let results = try!(tiles::render_blocks(frame.columns, frame.rows, &self.pool,
+                                                move |x0, y0, x1, y1, values: &mut Vec<(Sample, u64)>| {
+            if escape.subdivide {
+                escape.subdivide_block(&frame, x0, y0, x1, y1, values);
+            } else {
+                for y in y0..y1 {
+                    escape.iterate_span(&frame, y, x0, x1, values);
+                }
+            }
+        }, job));
+
+        let saved = results.iter().fold(0u64, |n, &(_, saved)| n + saved);
+        Ok((results.into_iter().map(|(sample, _)| sample).collect(), saved))
+    }
+
+    // Colour each pixel by the root Newton's method takes it to, one hue per
+    // root, darkening with the number of steps it took to get there
+    fn render_newton(&self, coefficients: &[Complex], frame: &Frame, max_iteration: u32,
+                     job: &Job) -> Result<Vec<RGB8>, Cancelled> {
+
+        let poly = Polynomial::new(coefficients);
+        let roots = poly.roots();
+        println!("            newton: roots {:?}", roots);
+
+        let frame = *frame;
+
+        tiles::render(frame.columns, frame.rows, &self.pool, move |px, py| {
+            newton_colour(&poly, &roots, frame.centre + frame.scale_coords(px, py), max_iteration)
+        }, job)
+    }
+
+    // Escape of each pixel in a deep zoom, by perturbation against a reference
+    // orbit at the centre.  Glitched pixels are rebased onto new references
+    // picked inside each glitched blob until none remain.  A blob that its own
+    // reference repairs none of is set aside, so that the next reference
+    // goes to a different blob.
+    fn iterate_perturbed(&self, frame: &Frame, max_iteration: u32,
+                         job: &Job) -> Result<Vec<Sample>, Cancelled> {
+
+        let frame = *frame;
+        let (width, height) = (frame.columns, frame.rows);
+
+        let npixels = (width*height) as usize;
+        let mut samples = vec![Sample::new(0, Complex::zero(), None, max_iteration); npixels];
+        let mut glitched: Vec<Option<f64>> = vec![None; npixels];
+        let mut remaining = 0;
+
+        let bailout = self.bailout();
+        let primary = Arc::new(ReferenceOrbit::compute(&self.centre_re, &self.centre_im, max_iteration, bailout));
+        println!("            deep zoom: reference orbit of {} iterations at {} bits",
+                 primary.len(), self.centre_re.precision() * 32);
+
+        // Probe a grid over the frame, edges and interior, to bound the series
+        let mut probes = Vec::with_capacity((SERIES_PROBES*SERIES_PROBES) as usize);
+        for j in 0..SERIES_PROBES {
+            for i in 0..SERIES_PROBES {
+                let (px, py) = (i*(width-1) / (SERIES_PROBES-1), j*(height-1) / (SERIES_PROBES-1));
+                probes.push(frame.scale_coords(px, py));
+            }
+        }
+        let series = Arc::new(primary.series(&probes, frame.pixel_size(), max_iteration));
+        println!("            series approximation skips {} iterations", series.skip());
+
+        let first = {
+            let primary = primary.clone();
+            let series = series.clone();
+            try!(tiles::render(width, height, &self.pool, move |px, py| {
+                primary.iterate(frame.scale_coords(px, py), &series, max_iteration)
+            }, job))
+        };
+        for (i, result) in first.into_iter().enumerate() {
+            match result {
+                Perturbed::Done(iteration, z, dz) => {
+                    samples[i] = Sample::new(iteration, z, Some(dz), max_iteration);
+                },
+                Perturbed::Glitched(size) => {
+                    glitched[i] = Some(size);
+                    remaining += 1;
+                },
+            }
+        }
+
+        let mut stuck = vec![false; npixels];
+        let mut references = 1;
+        while references < MAX_REFERENCES {
+            let seed = match (0..npixels).position(|i| glitched[i].is_some() && !stuck[i]) {
+                Some(seed) => seed,
+                None => break,
+            };
+            try!(job.check());
+            let (target, blob) = perturb::blob_reference(&glitched, width, height, seed);
+            let offset = frame.scale_coords(target as u32 % width, target as u32 / width);
+
+            let ref_re = &self.centre_re + &BigFixed::from_f64(offset.re, self.centre_re.precision());
+            let ref_im = &self.centre_im + &BigFixed::from_f64(offset.im, self.centre_im.precision());
+            let orbit = ReferenceOrbit::compute(&ref_re, &ref_im, max_iteration, bailout);
+            references += 1;
+
+            remaining = 0;
+            for i in 0..npixels {
+                if glitched[i].is_some() {
+                    let delta = frame.scale_coords(i as u32 % width, i as u32 / width);
+                    match orbit.iterate(delta - offset, &SeriesApproximation::none(), max_iteration) {
+                        Perturbed::Done(iteration, z, dz) => {
+                            samples[i] = Sample::new(iteration, z, Some(dz), max_iteration);
+                            glitched[i] = None;
+                        },
+                        Perturbed::Glitched(size) => {
+                            glitched[i] = Some(size);
+                            remaining += 1;
+                        },
+                    }
+                }
+            }
+            if blob.iter().all(|&i| glitched[i].is_some()) {
+                for i in blob {
+                    stuck[i] = true;
+                }
+            }
+            println!("            reference {}: {} glitched pixels remain", references, remaining);
+        }
+
+        // Out of references, so settle for the unchecked result
+        if remaining > 0 {
+            for i in 0..npixels {
+                if glitched[i].is_some() {
+                    let delta = frame.scale_coords(i as u32 % width, i as u32 / width);
+                    let (iteration, z, dz) = primary.iterate_unchecked(delta, frame.centre + delta, &series, max_iteration);
+                    samples[i] = Sample::new(iteration, z, Some(dz), max_iteration);
+                }
+            }
+        }
+
+        Ok(samples)
     }
 }
 
 //----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::VecDeque;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    use bignum::BigFixed;
+    use complex::Complex;
+    use job::Job;
+    use perturb::{ReferenceOrbit, Perturbed, SeriesApproximation};
+    use protocol::{EngineCommand, EngineStatus, Fractal, RenderType, Strategy};
+    use simd;
+    use super::{MandelEngine, Sample, PERIOD_TOLERANCE, in_main_bulbs};
+
+    // Everything the engine sends back for the given batches of commands,
+    // each sent once the last one has rendered.  Each batch should end in
+    // a Render.  The first is queued up before the engine starts, so it is
+    // all taken in together.
+    fn run(width: u32, height: u32, batches: Vec<Vec<EngineCommand>>) -> Vec<EngineStatus> {
+        let (cmd_tx, cmd_rx) = channel();
+        let (status_tx, status_rx) = channel();
+        let mut idle = Some((cmd_rx, status_tx));
+        let mut engine = None;
+
+        // A Shutdown sent any sooner would cancel the render
+        let mut statuses = Vec::new();
+        for batch in batches {
+            for cmd in batch {
+                cmd_tx.send(cmd).unwrap();
+            }
+            if let Some((cmd_rx, status_tx)) = idle.take() {
+                engine = Some(thread::spawn(move || {
+                    let mut engine = MandelEngine::new(width, height);
+                    engine.serve(&cmd_rx, &status_tx);
+                }));
+            }
+            loop {
+                let status = status_rx.recv().unwrap();
+                let done = matches!(status, EngineStatus::RenderComplete(..));
+                statuses.push(status);
+                if done {
+                    break;
+                }
+            }
+        }
+        cmd_tx.send(EngineCommand::Shutdown).unwrap();
+        if let Some(engine) = engine {
+            engine.join().unwrap();
+        }
+        statuses
+    }
+
+    // Image of the last render among the statuses
+    fn last_image(statuses: Vec<EngineStatus>) -> Vec<u8> {
+        statuses.into_iter().filter_map(|status| match status {
+            EngineStatus::RenderComplete(_, _, pixels) => Some(pixels),
+            _ => None,
+        }).next_back().unwrap()
+    }
+
+    // Full render of a width x height frame after the given commands
+    fn render(width: u32, height: u32, mut commands: Vec<EngineCommand>) -> Vec<u8> {
+        commands.push(EngineCommand::Render(RenderType::FullRender));
+        last_image(run(width, height, vec![commands]))
+    }
+
+    // Escape count of c = re + im*i, iterated directly at full precision
+    fn exact_count(re: &BigFixed, im: &BigFixed, max_iteration: u32) -> u32 {
+        let prec = re.precision();
+        let mut zr = BigFixed::zero(prec);
+        let mut zi = BigFixed::zero(prec);
+        for iteration in 0..max_iteration {
+            let zr2 = &zr * &zr;
+            let zi2 = &zi * &zi;
+            if (&zr2 + &zi2).to_f64() >= 4.0 {
+                return iteration;
+            }
+            let zri = &zr * &zi;
+            zi = &(&zri + &zri) + im;
+            zr = &(&zr2 - &zi2) + re;
+        }
+        max_iteration
+    }
+
+    // Escape counts of a w x h deep frame by perturbation, and by iterating
+    // each pixel directly
+    fn deep_counts(re: &str, im: &str, width: f64, w: u32, h: u32,
+                   max_iteration: u32) -> (Vec<u32>, Vec<u32>) {
+        let mut engine = MandelEngine::new(w, h);
+        assert!(engine.set_centre(re, im, width));
+        let frame = engine.frame(w, h);
+
+        let (_cmd_tx, cmd_rx) = channel();
+        let (status_tx, _status_rx) = channel();
+        let job = Job::new(0, &cmd_rx, &status_tx, VecDeque::new());
+        let samples = engine.iterate_perturbed(&frame, max_iteration, &job).ok().unwrap();
+        let perturbed = samples.iter().map(|sample| sample.iteration).collect();
+
+        let prec = engine.centre_re.precision();
+        let mut exact = Vec::with_capacity((w*h) as usize);
+        for y in 0..h {
+            for x in 0..w {
+                let offset = frame.scale_coords(x, y);
+                let re = &engine.centre_re + &BigFixed::from_f64(offset.re, prec);
+                let im = &engine.centre_im + &BigFixed::from_f64(offset.im, prec);
+                exact.push(exact_count(&re, &im, max_iteration));
+            }
+        }
+        (perturbed, exact)
+    }
+
+    // Pixels that differ between two RGB images
+    fn differences(a: &[u8], b: &[u8]) -> usize {
+        a.chunks(3).zip(b.chunks(3)).filter(|&(p, q)| p != q).count()
+    }
+
+    // Every kernel follows the scalar loop bit for bit, whether orbits
+    // escape, are found to be periodic or start off inside the main bulbs
+    #[test]
+    fn simd_kernels_match_scalar_loop() {
+        let max_iteration = 500;
+        let escape = MandelEngine::new(64, 64).escape(max_iteration);
+
+        let mut pixels = Vec::new();
+        for j in 0..97 {
+            for i in 0..129 {
+                pixels.push(Complex::new(-2.2 + i as f64 * 0.025, -1.2 + j as f64 * 0.025));
+            }
+        }
+        let z0: Vec<(f64, f64)> = pixels.iter().map(|_| (0.0, 0.0)).collect();
+        let c: Vec<(f64, f64)> = pixels.iter().map(|p| (p.re, p.im)).collect();
+        let inside: Vec<bool> = pixels.iter().map(|&p| in_main_bulbs(p)).collect();
+        let expected: Vec<(Sample, u64)> = pixels.iter().map(|&p| escape.iterate(p)).collect();
+
+        let periodic = expected.iter().zip(inside.iter()).filter(|&(&(_, saved), &inside)| saved > 0 && !inside).count();
+        assert!(periodic > 0 && inside.iter().any(|&inside| inside));
+
+        for kernel in simd::kernels() {
+            let orbits = simd::iterate_with(kernel, &z0, &c, &inside, escape.bailout, PERIOD_TOLERANCE, max_iteration);
+            for (orbit, &(sample, saved)) in orbits.iter().zip(expected.iter()) {
+                let z = Complex::new(orbit.z_re, orbit.z_im);
+                let got = Sample::new(orbit.iteration, z, None, max_iteration);
+                assert_eq!((got.iteration, got.norm_sqr.to_bits(), orbit.saved),
+                           (sample.iteration, sample.norm_sqr.to_bits(), saved), "{:?} kernel", kernel);
+            }
+        }
+    }
+
+    // Cutting orbits short inside the main bulbs, or once they are seen to
+    // repeat, never changes a pixel's count from that of the plain loop
+    #[test]
+    fn interior_shortcuts_match_plain_loop() {
+        let views = [("-0.75", "0.0", 3.5), ("-1.0", "0.0", 0.5),
+                     ("-0.75", "0.1", 0.05), ("-1.7685", "0.0", 0.02)];
+        let (w, h, max_iteration) = (160, 120, 2000);
+        for &(re, im, width) in views.iter() {
+            let mut engine = MandelEngine::new(w, h);
+            assert!(engine.set_centre(re, im, width));
+            let frame = engine.frame(w, h);
+
+            let (_cmd_tx, cmd_rx) = channel();
+            let (status_tx, _status_rx) = channel();
+            let job = Job::new(0, &cmd_rx, &status_tx, VecDeque::new());
+            let (samples, saved) = engine.iterate_direct(&frame, max_iteration, &job).ok().unwrap();
+            assert!(saved > 0);
+
+            for (i, sample) in samples.iter().enumerate() {
+                let c = frame.centre + frame.scale_coords(i as u32 % w, i as u32 / w);
+                let mut z = Complex::zero();
+                let mut iteration = 0;
+                while z.norm_sqr() < 4.0 && iteration < max_iteration {
+                    z = z*z + c;
+                    iteration += 1;
+                }
+                assert_eq!(sample.iteration, iteration, "pixel {} at {} {} {}", i, re, im, width);
+            }
+        }
+    }
+
+    // However the tiles are shared out, every pixel comes out the same
+    #[test]
+    fn thread_count_does_not_change_the_frame() {
+        let julia = Fractal::Julia { c: Complex::new(-0.8, 0.156) };
+        for fractal in &[Fractal::Mandelbrot, julia] {
+            let frames: Vec<Vec<u8>> = [1, 3, 7].iter().map(|&threads| {
+                render(320, 240, vec![EngineCommand::SetFractal(fractal.clone()),
+                                      EngineCommand::SetThreads(threads)])
+            }).collect();
+            assert!(frames[0] == frames[1]);
+            assert!(frames[0] == frames[2]);
+        }
+    }
+
+    // The lines worked out after a zoom, and kept for the render that
+    // follows, have to be just as a fresh render would have them
+    #[test]
+    fn zoom_matches_fresh_render() {
+        let view = || vec![EngineCommand::SetCentre("-0.75".to_string(), "0.1".to_string(), 0.05)];
+        for &zoom_in in [true, false].iter() {
+            let zoom = || if zoom_in { EngineCommand::ZoomIn } else { EngineCommand::ZoomOut };
+            for &progressive in [false, true].iter() {
+                let typ = || if progressive { RenderType::ProgressiveRender } else { RenderType::FullRender };
+                let mut first = view();
+                first.push(EngineCommand::Render(RenderType::FullRender));
+                let zoomed = run(64, 48, vec![first, vec![zoom(), EngineCommand::Render(typ())]]);
+                let previews = zoomed.iter().filter(|status| matches!(**status, EngineStatus::PartialFrame(2, 0, _, _))).count();
+                assert_eq!(previews, 2, "{:?} {:?}", zoom(), typ());
+
+                let mut fresh = view();
+                fresh.push(zoom());
+                assert_eq!(differences(&last_image(zoomed), &render(64, 48, fresh)), 0, "{:?} {:?}", zoom(), typ());
+            }
+        }
+    }
+
+    // A pan moves the view by whole pixels, so the last frame is shifted
+    // across rather than rendered again, and comes out just as a fresh
+    // render would
+    #[test]
+    fn pan_matches_fresh_render() {
+        let view = || vec![EngineCommand::SetCentre("-0.75".to_string(), "0.1".to_string(), 0.05)];
+        for direction in 0..4 {
+            let pan = || match direction {
+                0 => EngineCommand::PanLeft,
+                1 => EngineCommand::PanRight,
+                2 => EngineCommand::PanUp,
+                _ => EngineCommand::PanDown,
+            };
+            for &progressive in [false, true].iter() {
+                let typ = || if progressive { RenderType::ProgressiveRender } else { RenderType::FullRender };
+                let mut first = view();
+                first.push(EngineCommand::Render(RenderType::FullRender));
+                let panned = run(64, 48, vec![first, vec![pan(), EngineCommand::Render(typ())]]);
+
+                // Only a render from scratch goes through the passes
+                let passes = panned.iter().filter(|status| matches!(**status, EngineStatus::PartialFrame(2, _, _, _))).count();
+                assert_eq!(passes, 0, "{:?} {:?}", pan(), typ());
+
+                let mut fresh = view();
+                fresh.push(pan());
+                assert_eq!(differences(&last_image(panned), &render(64, 48, fresh)), 0, "{:?} {:?}", pan(), typ());
+            }
+        }
+    }
+
+    // Renders queued up behind a pan are all dropped but the last, and
+    // nothing else of theirs is sent
+    #[test]
+    fn queued_renders_are_cancelled() {
+        let view = || vec![EngineCommand::SetCentre("-0.75".to_string(), "0.1".to_string(), 0.05)];
+        let mut commands = view();
+        commands.push(EngineCommand::Render(RenderType::FullRender));
+        commands.push(EngineCommand::PanLeft);
+        commands.push(EngineCommand::Render(RenderType::ProgressiveRender));
+        commands.push(EngineCommand::Render(RenderType::PreviewRender));
+        commands.push(EngineCommand::Render(RenderType::FullRender));
+        let statuses = run(64, 48, vec![commands]);
+
+        let mut cancelled = Vec::new();
+        for status in &statuses {
+            match *status {
+                EngineStatus::Cancelled(generation) => cancelled.push(generation),
+                EngineStatus::TileComplete(generation, _, _) | EngineStatus::PartialFrame(generation, _, _, _) |
+                EngineStatus::DistanceField(generation, _) | EngineStatus::Statistics(generation, _) |
+                EngineStatus::RenderComplete(generation, _, _) => assert_eq!(generation, 4, "{:?}", status),
+                _ => (),
+            }
+        }
+        assert_eq!(cancelled, vec![1, 2, 3]);
+
+        let mut panned = view();
+        panned.push(EngineCommand::PanLeft);
+        assert_eq!(differences(&last_image(statuses), &render(64, 48, panned)), 0);
+    }
+
+    // Subdivision fills a rectangle whose border is all one count with that
+    // count.  The set is connected, so nothing of it can hide inside such a
+    // border, but the border is only sampled at pixel centres: a filament
+    // thinner than a pixel can slip between two of them, and the pixels it
+    // crosses inside then take the border's count.  That can't be ruled
+    // out short of iterating every pixel, so up to one pixel in 10,000 may
+    // differ; these views lose at most 6 of 102,400.
+    #[test]
+    fn subdivide_matches_brute_force() {
+        let views = [("-0.75", "0.0", 3.5), ("-0.75", "0.1", 0.05),
+                     ("-1.7685", "0.0", 0.02), ("-0.1011", "0.9563", 0.01)];
+        for &(re, im, width) in views.iter() {
+            let view = || vec![EngineCommand::SetCentre(re.to_string(), im.to_string(), width)];
+            let brute = render(320, 320, view());
+            let mut commands = view();
+            commands.push(EngineCommand::SetStrategy(Strategy::Subdivide));
+            let subdivided = render(320, 320, commands);
+            let differ = differences(&brute, &subdivided);
+            assert!(differ <= 320*320 / 10000, "{} pixels differ at {} {} {}", differ, re, im, width);
+        }
+    }
+
+    // Over 800 iterations are skipped here, just short of where A collapses,
+    // so any error the series leaves shows up in the counts
+    #[test]
+    fn series_matches_direct_iteration() {
+        let (perturbed, exact) = deep_counts("-0.743643887037158", "0.131825904205312", 2e-12, 20, 15, 3000);
+        assert_eq!(perturbed, exact);
+    }
+
+    // The reference at the centre of this frame glitches a few pixels, which
+    // have to be repaired against other references.  Beyond 4000 iterations
+    // some pixels here change count when moved by 1e-13 of their width, so
+    // no f64 delta could be held to exact iteration there.
+    #[test]
+    fn rebasing_matches_direct_iteration() {
+        let (re, im, width) = ("-0.743643887037158", "0.131825904205312", 1e-13);
+        let (w, h, max_iteration) = (20, 15, 4000);
+
+        let mut engine = MandelEngine::new(w, h);
+        assert!(engine.set_centre(re, im, width));
+        let frame = engine.frame(w, h);
+        let primary = ReferenceOrbit::compute(&engine.centre_re, &engine.centre_im, max_iteration, 2.0);
+        let glitched = (0..w*h).filter(|&i| {
+            match primary.iterate(frame.scale_coords(i % w, i / w), &SeriesApproximation::none(), max_iteration) {
+                Perturbed::Glitched(_) => true,
+                Perturbed::Done(..) => false,
+            }
+        }).count();
+        assert!(glitched > 0);
+
+        let (perturbed, exact) = deep_counts(re, im, width, w, h, max_iteration);
+        assert_eq!(perturbed, exact);
+    }
+}
@@ -0,0 +1,485 @@
+//============================================================================
+//
+// A simple Mandelbrot image generator in Rust
+//
+// Formula expressions typed in by the user, eg. "z^3 + c*sin(z)"
+//
+// Copyright (c) 2014 Gavin Baker <gavinb@antonym.org>
+// Published under the MIT license
+//
+//============================================================================
+
+// Expressions are parsed once by recursive descent straight into a small
+// stack machine program, which is then run for every iteration of every
+// pixel.  The language is complex arithmetic:
+//
+//     numbers      2, 0.5, 1e-3, and imaginary 2i, 0.5i
+//     variables    z, c, pixel
+//     constants    i, pi, e
+//     operators    + - * / ^ and unary minus, with the usual precedence;
+//                  ^ binds right to left
+//     functions    sin cos tan sinh cosh tanh exp log sqrt abs conj re im
+
+use std::f64::consts;
+use std::fmt;
+use std::vec::Vec;
+
+use complex::Complex;
+
+//----------------------------------------------------------------------------
+
+// Evaluation stack size, and so the deepest nesting allowed, as eval sizes
+// its stack
+static STACK_SIZE: usize = 32;
+
+// Deepest nesting of brackets, minus signs and powers the parser will
+// follow, well within the stack of the thread running it
+static MAX_NESTING: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    // Byte offset into the formula
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "column {}: {}", self.position + 1, self.message)
+    }
+}
+
+//----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Func {
+    Sin, Cos, Tan, Sinh, Cosh, Tanh, Exp, Log, Sqrt, Abs, Conj, Re, Im,
+}
+
+impl Func {
+
+    fn lookup(name: &str) -> Option<Func> {
+        match name {
+            "sin" => Some(Func::Sin),
+            "cos" => Some(Func::Cos),
+            "tan" => Some(Func::Tan),
+            "sinh" => Some(Func::Sinh),
+            "cosh" => Some(Func::Cosh),
+            "tanh" => Some(Func::Tanh),
+            "exp" => Some(Func::Exp),
+            "log" | "ln" => Some(Func::Log),
+            "sqrt" => Some(Func::Sqrt),
+            "abs" => Some(Func::Abs),
+            "conj" => Some(Func::Conj),
+            "re" => Some(Func::Re),
+            "im" => Some(Func::Im),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, z: Complex) -> Complex {
+        match *self {
+            Func::Sin => z.sin(),
+            Func::Cos => z.cos(),
+            Func::Tan => z.sin() / z.cos(),
+            Func::Sinh => z.sinh(),
+            Func::Cosh => z.cosh(),
+            Func::Tanh => z.sinh() / z.cosh(),
+            Func::Exp => z.exp(),
+            Func::Log => z.ln(),
+            Func::Sqrt => z.sqrt(),
+            Func::Abs => Complex::new(z.norm(), 0.0),
+            Func::Conj => z.conj(),
+            Func::Re => Complex::new(z.re, 0.0),
+            Func::Im => Complex::new(z.im, 0.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Const(Complex),
+    Z,
+    C,
+    Pixel,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    // Power by a real constant, which is much cheaper than the general case
+    PowReal(f64),
+    Neg,
+    Call(Func),
+}
+
+//----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Imaginary(f64),
+    Ident(String),
+    Symbol(char),
+    End,
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (pos, ch) = chars[i];
+        if ch.is_whitespace() {
+            i += 1;
+        } else if ch.is_ascii_digit() || ch == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].1.is_ascii_digit() || chars[i].1 == '.') {
+                i += 1;
+            }
+            // An exponent needs digits after it, so "2*e" still means e
+            if i < chars.len() && (chars[i].1 == 'e' || chars[i].1 == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j].1 == '+' || chars[j].1 == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].1.is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].1.is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let end = if i < chars.len() { chars[i].0 } else { source.len() };
+            let value = match source[pos..end].parse::<f64>() {
+                Ok(value) => value,
+                Err(_) => return Err(ParseError { position: chars[start].0,
+                                                  message: format!("invalid number '{}'", &source[pos..end]) }),
+            };
+            // A trailing i on its own makes the number imaginary
+            let imaginary = i < chars.len() && chars[i].1 == 'i' &&
+                !(i + 1 < chars.len() && (chars[i+1].1.is_alphanumeric() || chars[i+1].1 == '_'));
+            if imaginary {
+                tokens.push((Token::Imaginary(value), pos));
+                i += 1;
+            } else {
+                tokens.push((Token::Number(value), pos));
+            }
+        } else if ch.is_alphabetic() || ch == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].1.is_alphanumeric() || chars[i].1 == '_') {
+                i += 1;
+            }
+            let end = if i < chars.len() { chars[i].0 } else { source.len() };
+            tokens.push((Token::Ident(source[chars[start].0..end].to_string()), pos));
+        } else if "+-*/^(),".contains(ch) {
+            tokens.push((Token::Symbol(ch), pos));
+            i += 1;
+        } else {
+            return Err(ParseError { position: pos, message: format!("unexpected character '{}'", ch) });
+        }
+    }
+
+    tokens.push((Token::End, source.len()));
+    Ok(tokens)
+}
+
+//----------------------------------------------------------------------------
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+    program: Vec<Op>,
+    // Where in the formula each op of the program came from
+    positions: Vec<usize>,
+    nesting: usize,
+}
+
+impl Parser {
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next].0
+    }
+
+    fn position(&self) -> usize {
+        self.tokens[self.next].1
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        let found = match *self.peek() {
+            Token::End => "end of formula".to_string(),
+            Token::Number(x) => format!("'{}'", x),
+            Token::Imaginary(x) => format!("'{}i'", x),
+            Token::Ident(ref name) => format!("'{}'", name),
+            Token::Symbol(ch) => format!("'{}'", ch),
+        };
+        Err(ParseError { position: self.position(), message: format!("{}, found {}", message, found) })
+    }
+
+    fn emit(&mut self, op: Op, position: usize) {
+        self.program.push(op);
+        self.positions.push(position);
+    }
+
+    fn accept(&mut self, symbol: char) -> bool {
+        if *self.peek() == Token::Symbol(symbol) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    // The single constant the program has produced since `start`, if any
+    fn constant_since(&self, start: usize) -> Option<Complex> {
+        if self.program.len() == start + 1 {
+            if let Op::Const(w) = self.program[start] {
+                return Some(w);
+            }
+        }
+        None
+    }
+
+    // expr := term (('+'|'-') term)*
+    fn expr(&mut self) -> Result<(), ParseError> {
+        try!(self.term());
+        loop {
+            let position = self.position();
+            if self.accept('+') {
+                try!(self.term());
+                self.emit(Op::Add, position);
+            } else if self.accept('-') {
+                try!(self.term());
+                self.emit(Op::Sub, position);
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    // term := unary (('*'|'/') unary)*
+    fn term(&mut self) -> Result<(), ParseError> {
+        try!(self.unary());
+        loop {
+            let position = self.position();
+            if self.accept('*') {
+                try!(self.unary());
+                self.emit(Op::Mul, position);
+            } else if self.accept('/') {
+                try!(self.unary());
+                self.emit(Op::Div, position);
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    // unary := '-' unary | power
+    //
+    // Every way of nesting one expression in another comes back through
+    // here, so this is where the depth is kept in check
+    fn unary(&mut self) -> Result<(), ParseError> {
+        if self.nesting == MAX_NESTING {
+            return Err(ParseError { position: self.position(), message: "formula is nested too deeply".to_string() });
+        }
+        self.nesting += 1;
+        let result = self.unary_nested();
+        self.nesting -= 1;
+        result
+    }
+
+    fn unary_nested(&mut self) -> Result<(), ParseError> {
+        let position = self.position();
+        if self.accept('-') {
+            let start = self.program.len();
+            try!(self.unary());
+            match self.constant_since(start) {
+                Some(w) => self.program[start] = Op::Const(-w),
+                None => self.emit(Op::Neg, position),
+            }
+            Ok(())
+        } else {
+            self.power()
+        }
+    }
+
+    // power := primary ('^' unary)?
+    fn power(&mut self) -> Result<(), ParseError> {
+        try!(self.primary());
+        let position = self.position();
+        if self.accept('^') {
+            let start = self.program.len();
+            try!(self.unary());
+            match self.constant_since(start) {
+                Some(w) if w.im == 0.0 => {
+                    self.program.pop();
+                    self.positions.pop();
+                    self.emit(Op::PowReal(w.re), position);
+                },
+                _ => self.emit(Op::Pow, position),
+            }
+        }
+        Ok(())
+    }
+
+    // primary := number | name | name '(' expr ')' | '(' expr ')'
+    fn primary(&mut self) -> Result<(), ParseError> {
+        let position = self.position();
+        match self.peek().clone() {
+            Token::Number(x) => {
+                self.next += 1;
+                self.emit(Op::Const(Complex::new(x, 0.0)), position);
+            },
+            Token::Imaginary(y) => {
+                self.next += 1;
+                self.emit(Op::Const(Complex::new(0.0, y)), position);
+            },
+            Token::Ident(name) => {
+                self.next += 1;
+                if self.accept('(') {
+                    let func = match Func::lookup(&name) {
+                        Some(func) => func,
+                        None => return Err(ParseError { position: position,
+                                                        message: format!("unknown function '{}'", name) }),
+                    };
+                    try!(self.expr());
+                    if !self.accept(')') {
+                        return self.error("expected ')'");
+                    }
+                    self.emit(Op::Call(func), position);
+                } else {
+                    let op = match name.as_ref() {
+                        "z" => Op::Z,
+                        "c" => Op::C,
+                        "pixel" => Op::Pixel,
+                        "i" => Op::Const(Complex::new(0.0, 1.0)),
+                        "pi" => Op::Const(Complex::new(consts::PI, 0.0)),
+                        "e" => Op::Const(Complex::new(consts::E, 0.0)),
+                        _ => return Err(ParseError { position: position,
+                                                     message: format!("unknown variable '{}'", name) }),
+                    };
+                    self.emit(op, position);
+                }
+            },
+            Token::Symbol('(') => {
+                self.next += 1;
+                try!(self.expr());
+                if !self.accept(')') {
+                    return self.error("expected ')'");
+                }
+            },
+            _ => return self.error("expected a number, variable or '('"),
+        }
+        Ok(())
+    }
+}
+
+//----------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct Expression {
+    source: String,
+    program: Vec<Op>,
+}
+
+impl Expression {
+
+    pub fn parse(source: &str) -> Result<Expression, ParseError> {
+        let tokens = try!(tokenize(source));
+        let mut parser = Parser { tokens: tokens, next: 0, program: Vec::new(), positions: Vec::new(), nesting: 0 };
+        try!(parser.expr());
+        if *parser.peek() != Token::End {
+            return parser.error("expected an operator");
+        }
+
+        // Check the program fits the evaluation stack
+        let mut depth = 0;
+        for (op, &position) in parser.program.iter().zip(parser.positions.iter()) {
+            match *op {
+                Op::Const(_) | Op::Z | Op::C | Op::Pixel => depth += 1,
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow => depth -= 1,
+                Op::PowReal(_) | Op::Neg | Op::Call(_) => (),
+            }
+            if depth > STACK_SIZE {
+                return Err(ParseError { position: position, message: "formula is nested too deeply".to_string() });
+            }
+        }
+
+        Ok(Expression { source: source.to_string(), program: parser.program })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn eval(&self, z: Complex, c: Complex, pixel: Complex) -> Complex {
+        let mut stack = [Complex::zero(); 32];
+        let mut top = 0;
+
+        for op in self.program.iter() {
+            match *op {
+                Op::Const(w) => { stack[top] = w; top += 1; },
+                Op::Z => { stack[top] = z; top += 1; },
+                Op::C => { stack[top] = c; top += 1; },
+                Op::Pixel => { stack[top] = pixel; top += 1; },
+                Op::Add => { top -= 1; stack[top-1] = stack[top-1] + stack[top]; },
+                Op::Sub => { top -= 1; stack[top-1] = stack[top-1] - stack[top]; },
+                Op::Mul => { top -= 1; stack[top-1] = stack[top-1] * stack[top]; },
+                Op::Div => { top -= 1; stack[top-1] = stack[top-1] / stack[top]; },
+                Op::Pow => { top -= 1; stack[top-1] = stack[top-1].powc(stack[top]); },
+                Op::PowReal(d) => stack[top-1] = stack[top-1].powf(d),
+                Op::Neg => stack[top-1] = -stack[top-1],
+                Op::Call(func) => stack[top-1] = func.apply(stack[top-1]),
+            }
+        }
+
+        stack[0]
+    }
+}
+
+//----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use complex::Complex;
+    use super::{Expression, MAX_NESTING};
+
+    fn nested(open: &str, inner: &str, close: &str, depth: usize) -> String {
+        format!("{}{}{}", open.repeat(depth), inner, close.repeat(depth))
+    }
+
+    #[test]
+    fn evaluates_formulas() {
+        let formula = Expression::parse("z^2 + c*sin(pixel) - -2/i").unwrap();
+        let (z, c, pixel) = (Complex::new(0.5, -1.0), Complex::new(0.25, 0.5), Complex::new(1.0, 0.0));
+        let expected = z*z + c*pixel.sin() - Complex::new(-2.0, 0.0) / Complex::new(0.0, 1.0);
+        let w = formula.eval(z, c, pixel);
+        assert!((w - expected).norm() < 1e-12, "{:?} != {:?}", w, expected);
+    }
+
+    // Nesting is limited while parsing, so deep formulas fail with an error
+    // at the offending token rather than overflowing the stack
+    #[test]
+    fn rejects_deep_nesting() {
+        let ok = nested("(", "z", ")", MAX_NESTING - 1);
+        assert!(Expression::parse(&ok).is_ok());
+
+        for &(open, close) in [("(", ")"), ("sin(", ")"), ("-", ""), ("z^", "")].iter() {
+            let formula = nested(open, "z", close, 100000);
+            let error = Expression::parse(&formula).unwrap_err();
+            assert_eq!(error.message, "formula is nested too deeply");
+            assert_eq!(error.position, open.len() * MAX_NESTING, "{}", open);
+        }
+    }
+
+    #[test]
+    fn reports_where_the_stack_runs_out() {
+        let ok = nested("z+(", "z", ")", 31);
+        assert!(Expression::parse(&ok).is_ok());
+        let error = Expression::parse(&nested("z+(", "z", ")", 40)).unwrap_err();
+        assert_eq!(error.message, "formula is nested too deeply");
+        assert_eq!(error.position, 3 * 32);
+    }
+}
@@ -0,0 +1,179 @@
+//============================================================================
+//
+// A simple Mandelbrot image generator in Rust
+//
+// Escape-time formulas
+//
+// Copyright (c) 2014 Gavin Baker <gavinb@antonym.org>
+// Published under the MIT license
+//
+//============================================================================
+
+// The engine iterates any FractalFormula, one pixel at a time:
+//
+//     let (mut z, c) = formula.init(pixel);
+//     while !formula.bailout(z, radius) {
+//         z = formula.step(z, c);
+//     }
+//
+// To add a formula of your own, implement the trait and hand it to
+// MandelEngine::set_formula.
+
+use complex::Complex;
+use expr::Expression;
+use protocol::{Fractal, Variant};
+
+//----------------------------------------------------------------------------
+
+pub trait FractalFormula: Send + Sync {
+
+    // Starting z, and the constant c, for the point `pixel`
+    fn init(&self, pixel: Complex) -> (Complex, Complex);
+
+    // One iteration, z[n+1] from z[n]
+    fn step(&self, z: Complex, c: Complex) -> Complex;
+
+    // True once z has left the circle of the given radius for good
+    fn bailout(&self, z: Complex, radius: f64) -> bool {
+        z.norm_sqr() >= radius*radius
+    }
+
+    // Power of z that dominates the step far from the origin, for
+    // working out fractional escape counts
+    fn degree(&self) -> f64 {
+        2.0
+    }
+
+    // Derivative dz/dc (or dz/dz0 for Julia sets) at the start of the orbit
+    fn init_derivative(&self) -> Complex {
+        Complex::zero()
+    }
+
+    // The derivative at z[n+1], from z[n] and the derivative at z[n], or
+    // None if the formula doesn't track it
+    fn derivative(&self, _z: Complex, _dz: Complex) -> Option<Complex> {
+        None
+    }
+}
+
+//----------------------------------------------------------------------------
+
+// z^d + c in any of the variants, for both Mandelbrot and Julia sets
+pub struct EscapeTime {
+    julia: Option<Complex>,
+    exponent: f64,
+    variant: Variant,
+}
+
+impl EscapeTime {
+
+    // None for fractals that are not escape time
+    pub fn new(fractal: &Fractal, exponent: f64, variant: Variant) -> Option<EscapeTime> {
+        let julia = match *fractal {
+            Fractal::Mandelbrot => None,
+            Fractal::Julia { c } => Some(c),
+            Fractal::Newton { .. } => return None,
+        };
+        Some(EscapeTime { julia: julia, exponent: exponent, variant: variant })
+    }
+
+    // The plain z^2 + c, which has shortcuts elsewhere
+    pub fn is_classic(&self) -> bool {
+        self.exponent == 2.0 && self.variant == Variant::Classic
+    }
+}
+
+// z^d, folded as the variant requires
+fn variant_power(variant: Variant, z: Complex, d: f64) -> Complex {
+    match variant {
+        Variant::Classic => z.powf(d),
+        Variant::BurningShip => Complex::new(z.re.abs(), z.im.abs()).powf(d),
+        Variant::Tricorn => z.conj().powf(d),
+        Variant::Celtic => {
+            let w = z.powf(d);
+            Complex::new(w.re.abs(), w.im)
+        },
+    }
+}
+
+impl FractalFormula for EscapeTime {
+
+    fn init(&self, pixel: Complex) -> (Complex, Complex) {
+        match self.julia {
+            None => (Complex::zero(), pixel),
+            Some(c) => (pixel, c),
+        }
+    }
+
+    fn step(&self, z: Complex, c: Complex) -> Complex {
+        if self.is_classic() {
+            z*z + c
+        } else {
+            variant_power(self.variant, z, self.exponent) + c
+        }
+    }
+
+    fn degree(&self) -> f64 {
+        self.exponent
+    }
+
+    fn init_derivative(&self) -> Complex {
+        match self.julia {
+            None => Complex::zero(),
+            Some(_) => Complex::new(1.0, 0.0),
+        }
+    }
+
+    // d(z^d + c) = d*z^(d-1)*dz (+ 1 when differentiating by c).  The
+    // folded variants are not analytic, so have no derivative.
+    fn derivative(&self, z: Complex, dz: Complex) -> Option<Complex> {
+        if self.variant != Variant::Classic {
+            return None;
+        }
+        let dzn = (z.powf(self.exponent - 1.0) * dz).scale(self.exponent);
+        match self.julia {
+            None => Some(dzn + Complex::new(1.0, 0.0)),
+            Some(_) => Some(dzn),
+        }
+    }
+}
+
+//----------------------------------------------------------------------------
+
+// z[n+1] = an expression in z, c and pixel
+pub struct ExpressionFormula {
+    expression: Expression,
+    julia: Option<Complex>,
+}
+
+impl ExpressionFormula {
+
+    // None for fractals that are not escape time
+    pub fn new(expression: &Expression, fractal: &Fractal) -> Option<ExpressionFormula> {
+        let julia = match *fractal {
+            Fractal::Mandelbrot => None,
+            Fractal::Julia { c } => Some(c),
+            Fractal::Newton { .. } => return None,
+        };
+        Some(ExpressionFormula { expression: expression.clone(), julia: julia })
+    }
+}
+
+impl FractalFormula for ExpressionFormula {
+
+    // The orbit carries the pixel as its constant, so the expression can
+    // see both it and the Julia constant
+    fn init(&self, pixel: Complex) -> (Complex, Complex) {
+        match self.julia {
+            None => (Complex::zero(), pixel),
+            Some(_) => (pixel, pixel),
+        }
+    }
+
+    fn step(&self, z: Complex, pixel: Complex) -> Complex {
+        let c = self.julia.unwrap_or(pixel);
+        self.expression.eval(z, c, pixel)
+    }
+}
+
+//----------------------------------------------------------------------------
@@ -22,8 +22,8 @@ use glium::glutin::{Event,VirtualKeyCode,ElementState};
 use glium::backend::glutin_backend::GlutinFacade;
 use image;
 
-use engine::MandelEngine;
-use protocol::{RenderType, EngineCommand, EngineStatus, PREVIEW_WIDTH, PREVIEW_HEIGHT};
+use mandelrust::engine::MandelEngine;
+use mandelrust::protocol::{RenderType, EngineCommand, EngineStatus, PREVIEW_WIDTH, PREVIEW_HEIGHT};
 use shaders;
 
 //----------------------------------------------------------------------------
@@ -39,6 +39,8 @@ pub struct WindowController<'a> {
     image_buf: image::DynamicImage,
     shader_program: glium::Program,
     texture: glium::Texture2d,
+    // Generation of the last render asked for
+    generation: u64,
 }
 
 impl<'a> WindowController<'a> {
@@ -78,6 +80,7 @@ impl<'a> WindowController<'a> {
                                         image_buf: image_buf,
                                         shader_program: program,
                                         texture: texture,
+                                        generation: 0,
         };
 
         wc
@@ -116,10 +119,12 @@ impl<'a> WindowController<'a> {
 
         implement_vertex!(Vertex, position, texcoord);
 
-        let vertex1 = Vertex { position: [-0.5, -0.5], texcoord: [1.0, 1.0] };
-        let vertex2 = Vertex { position: [ 0.0,  0.5], texcoord: [0.0, 1.0] };
-        let vertex3 = Vertex { position: [ 0.5, -0.25],texcoord: [1.0, 0.0] };
-        let shape = vec![vertex1, vertex2, vertex3];
+        // Two triangles covering the window, showing the whole texture
+        let bottom_left = Vertex { position: [-1.0, -1.0], texcoord: [0.0, 0.0] };
+        let bottom_right = Vertex { position: [ 1.0, -1.0], texcoord: [1.0, 0.0] };
+        let top_right = Vertex { position: [ 1.0,  1.0], texcoord: [1.0, 1.0] };
+        let top_left = Vertex { position: [-1.0,  1.0], texcoord: [0.0, 1.0] };
+        let shape = vec![bottom_left, bottom_right, top_right, bottom_left, top_right, top_left];
 
         let vertex_buffer = glium::VertexBuffer::new(self.window, &shape).unwrap();
         let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
@@ -146,40 +151,64 @@ impl<'a> WindowController<'a> {
 
         let cmd_ch = self.chan_wc_to_engine.as_ref().expect("no chan_wc_to_engine");
 
-        cmd_ch.send(EngineCommand::Render(RenderType::PreviewRender));
-        cmd_ch.send(EngineCommand::Render(RenderType::FullRender));
+        cmd_ch.send(EngineCommand::Render(RenderType::ProgressiveRender));
+        self.generation += 1;
+    }
+
+    // Replace the texture with an RGB frame from the engine, top row first
+    fn show_frame(&mut self, width: u32, height: u32, pixels: &[u8]) {
+        let mut rgba = Vec::with_capacity(pixels.len() / 3 * 4);
+        for rgb in pixels.chunks(3) {
+            rgba.extend_from_slice(rgb);
+            rgba.push(255);
+        }
+        let image = glium::texture::RawImage2d::from_raw_rgba_reversed(rgba, (width, height));
+        self.texture = glium::texture::Texture2d::new(self.window, image).unwrap();
     }
 
     pub fn maybe_update_display(&mut self) {
-        match self.chan_wc_from_engine {
-            Some(ref ch) => {
-                let status_msg = ch.try_recv();
-                match status_msg {
-                    Ok(status) =>
-                        match status {
-                            EngineStatus::Startup => println!("Startup..."),
-                            EngineStatus::Processing(progress) => println!("Processing {}", progress),
-                            EngineStatus::RenderComplete(typ, img) => {
-                                println!("Render Complete!");
-                                //self.image = Some(img);
-                                let imgbuf = img.as_ptr();
-                                match typ {
-                                    RenderType::FullRender => {
-                                        println!("fullRender {} {}", self.buffer_width, self.buffer_height);
-                                        // TODO: update image and texture (full size)
-                                    },
-                                    RenderType::PreviewRender => {
-                                        println!("Preview {} {}", PREVIEW_WIDTH, PREVIEW_HEIGHT);
-                                        // TODO: update image and texture (preview size)
-                                    },
-                                };
+        let status_msg = match self.chan_wc_from_engine {
+            Some(ref ch) => ch.try_recv().ok(),
+            None => None,
+        };
+        match status_msg {
+            Some(status) =>
+                match status {
+                    EngineStatus::Startup => println!("Startup..."),
+                    EngineStatus::Processing(progress) => println!("Processing {}", progress),
+                    EngineStatus::TileComplete(_, done, total) => println!("Tile {}/{}", done, total),
+                    EngineStatus::PartialFrame(generation, pass, passes, img) if generation == self.generation => {
+                        println!("Pass {}/{} {} {}", pass, passes, self.buffer_width, self.buffer_height);
+                        let (w, h) = (self.buffer_width, self.buffer_height);
+                        self.show_frame(w, h, &img);
+                    },
+                    EngineStatus::PartialFrame(..) => (),
+                    EngineStatus::DistanceField(..) => (),
+                    EngineStatus::Statistics(generation, stats) if generation == self.generation => {
+                        println!("Iterations {}, saved {}", stats.iterations, stats.iterations_saved);
+                    },
+                    EngineStatus::Statistics(..) => (),
+                    EngineStatus::RenderComplete(generation, _, _) if generation < self.generation => {
+                        println!("Dropping render {}", generation);
+                    },
+                    EngineStatus::Cancelled(generation) => println!("Render {} cancelled", generation),
+                    EngineStatus::RenderComplete(_, typ, img) => {
+                        println!("Render Complete!");
+                        let (w, h) = match typ {
+                            RenderType::FullRender | RenderType::ProgressiveRender => {
+                                println!("fullRender {} {}", self.buffer_width, self.buffer_height);
+                                (self.buffer_width, self.buffer_height)
                             },
-                            EngineStatus::Error(code) => println!("Error {}", code),
-                        },
-                    _ => ()
-                }
-            },
-            None => (),
+                            RenderType::PreviewRender => {
+                                println!("Preview {} {}", PREVIEW_WIDTH, PREVIEW_HEIGHT);
+                                (PREVIEW_WIDTH as u32, PREVIEW_HEIGHT as u32)
+                            },
+                        };
+                        self.show_frame(w, h, &img);
+                    },
+                    EngineStatus::Error(code) => println!("Error {}", code),
+                },
+            None => ()
         }
     }
 
@@ -188,46 +217,52 @@ impl<'a> WindowController<'a> {
         let cmd_ch = self.chan_wc_to_engine.as_ref().expect("no chan_wc_to_engine");
         match event {
             Event::KeyboardInput(ElementState::Released, _, Some(keycode)) => {
-                match keycode {
-                    VirtualKeyCode::Space => {
-                        cmd_ch.send(EngineCommand::Render(RenderType::FullRender));
-                    },
+                let render = match keycode {
+                    VirtualKeyCode::Space => Some(RenderType::FullRender),
                     VirtualKeyCode::Equals => {
                         cmd_ch.send(EngineCommand::ZoomIn);
-                        cmd_ch.send(EngineCommand::Render(RenderType::PreviewRender));
+                        Some(RenderType::ProgressiveRender)
                     },
                     VirtualKeyCode::Minus => {
                         cmd_ch.send(EngineCommand::ZoomOut);
-                        cmd_ch.send(EngineCommand::Render(RenderType::PreviewRender));
+                        Some(RenderType::ProgressiveRender)
                     },
                     VirtualKeyCode::Left => {
                         cmd_ch.send(EngineCommand::PanLeft);
-                        cmd_ch.send(EngineCommand::Render(RenderType::PreviewRender));
+                        Some(RenderType::ProgressiveRender)
                     },
                     VirtualKeyCode::Right => {
                         cmd_ch.send(EngineCommand::PanRight);
-                        cmd_ch.send(EngineCommand::Render(RenderType::PreviewRender));
+                        Some(RenderType::ProgressiveRender)
                     },
                     VirtualKeyCode::Up => {
                         cmd_ch.send(EngineCommand::PanUp);
-                        cmd_ch.send(EngineCommand::Render(RenderType::PreviewRender));
+                        Some(RenderType::ProgressiveRender)
                     },
                     VirtualKeyCode::Down => {
                         cmd_ch.send(EngineCommand::PanDown);
-                        cmd_ch.send(EngineCommand::Render(RenderType::PreviewRender));
+                        Some(RenderType::ProgressiveRender)
                     },
                     VirtualKeyCode::Escape => {
                         cmd_ch.send(EngineCommand::Shutdown);
 //                        self.window.set_should_close(true);
+                        None
                     },
                     VirtualKeyCode::S => {
                     //     match self.image {
                     //         Some(ref img) => save_as_pgm(img, self.buffer_width, self.buffer_height, "test.pgm"),
                     //         _ => (),
                     //     }
+                        None
                     },
-                    _ => {}
+                    _ => None
                 };
+
+                // Anything still to come from earlier renders is now stale
+                if let Some(typ) = render {
+                    cmd_ch.send(EngineCommand::Render(typ));
+                    self.generation += 1;
+                }
             },
             _ => {},
         }
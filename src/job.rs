@@ -0,0 +1,100 @@
+//============================================================================
+//
+// A simple Mandelbrot image generator in Rust
+//
+// Keeping an eye on the command channel while a render runs
+//
+// Copyright (c) 2014 Gavin Baker <gavinb@antonym.org>
+// Published under the MIT license
+//
+//============================================================================
+
+// Each Render command starts a new generation of results, numbered from 1
+// in the order the engine receives them.  Commands that arrive while a
+// render is running are queued up to be handled once it is over, and as
+// soon as a later Render or a Shutdown turns up the render is stale: the
+// workers drop it, and the engine moves straight on to the next one.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+
+use protocol::{EngineCommand, EngineStatus};
+
+// A render given up part way through
+#[derive(Debug)]
+pub struct Cancelled;
+
+pub struct Job<'a> {
+    generation: u64,
+    cmd_chan: &'a Receiver<EngineCommand>,
+    progress_chan: &'a Sender<EngineStatus>,
+    pending: RefCell<VecDeque<EngineCommand>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+// Whether a command makes any render before it pointless
+pub fn supersedes(cmd: &EngineCommand) -> bool {
+    matches!(*cmd, EngineCommand::Render(_) | EngineCommand::Shutdown)
+}
+
+//----------------------------------------------------------------------------
+
+impl<'a> Job<'a> {
+
+    // Start on a render, behind the commands already waiting
+    pub fn new(generation: u64, cmd_chan: &'a Receiver<EngineCommand>,
+               progress_chan: &'a Sender<EngineStatus>, pending: VecDeque<EngineCommand>) -> Job<'a> {
+        Job {
+            generation: generation,
+            cmd_chan: cmd_chan,
+            progress_chan: progress_chan,
+            pending: RefCell::new(pending),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn send(&self, status: EngineStatus) {
+        self.progress_chan.send(status).unwrap();
+    }
+
+    // Queue up any commands that have arrived, and give up if one of them
+    // makes this render stale, or if nobody is left to send them
+    pub fn check(&self) -> Result<(), Cancelled> {
+        let mut pending = self.pending.borrow_mut();
+        loop {
+            match self.cmd_chan.try_recv() {
+                Ok(cmd) => {
+                    if supersedes(&cmd) {
+                        self.cancelled.store(true, Ordering::SeqCst);
+                    }
+                    pending.push_back(cmd);
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.cancelled.store(true, Ordering::SeqCst);
+                    break;
+                },
+            }
+        }
+        if self.cancelled.load(Ordering::SeqCst) { Err(Cancelled) } else { Ok(()) }
+    }
+
+    // Set once the render is stale, for workers to watch
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    // Commands to handle next, in the order they arrived
+    pub fn into_pending(self) -> VecDeque<EngineCommand> {
+        self.pending.into_inner()
+    }
+}
+
+//----------------------------------------------------------------------------
@@ -0,0 +1,25 @@
+//============================================================================
+//
+// A simple Mandelbrot image generator in Rust
+//
+// Engine library, shared by the front-ends and open to other crates
+//
+// Copyright (c) 2014 Gavin Baker <gavinb@antonym.org>
+// Published under the MIT license
+//
+//============================================================================
+
+// The code keeps to its original Rust 2015 idiom: try! and explicit field names
+#![allow(deprecated, clippy::redundant_field_names)]
+
+pub mod bignum;
+pub mod complex;
+pub mod engine;
+pub mod expr;
+pub mod formula;
+pub mod job;
+pub mod newton;
+pub mod perturb;
+pub mod protocol;
+pub mod simd;
+pub mod tiles;
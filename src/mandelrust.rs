@@ -9,16 +9,18 @@
 //
 //============================================================================
 
+// The code keeps to its original Rust 2015 idiom: try! and explicit field names
+#![allow(deprecated, clippy::redundant_field_names)]
+
 #[macro_use]
 extern crate glium;
 extern crate glutin;
 extern crate image;
+extern crate mandelrust;
 
 use gui::WindowController;
 
 mod gui;
-mod engine;
-mod protocol;
 mod shaders;
 
 //----------------------------------------------------------------------------
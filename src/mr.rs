@@ -9,17 +9,285 @@
 //
 //============================================================================
 
+// The code keeps to its original Rust 2015 idiom: try! and explicit field names
+#![allow(deprecated, clippy::redundant_field_names)]
+
 use std::vec::Vec;
+use std::env;
 use std::fs::File;
+use std::process;
 use std::thread;
 use std::io::{Error, ErrorKind, Write};
+use std::path::Path;
 use std::sync::mpsc::{channel, Sender, Receiver};
 
-use engine::MandelEngine;
-use protocol::{RenderType, EngineCommand, EngineStatus, PREVIEW_WIDTH, PREVIEW_HEIGHT};
+extern crate image;
+extern crate mandelrust;
+
+use mandelrust::complex::Complex;
+use mandelrust::engine::{MandelEngine, DEFAULT_CENTRE_RE, DEFAULT_CENTRE_IM, DEFAULT_WIDTH};
+use mandelrust::protocol;
+use mandelrust::protocol::{RenderType, EngineCommand, EngineStatus, EngineError, Fractal, Variant, Colouring};
+use mandelrust::protocol::{RenderParams, Strategy, Supersampling};
+use mandelrust::protocol::{PREVIEW_WIDTH, PREVIEW_HEIGHT};
+
+//----------------------------------------------------------------------------
+
+struct Options {
+    centre: Option<(String, String)>,
+    width: Option<f64>,
+    fractal: Fractal,
+    exponent: f64,
+    variant: Variant,
+    formula: Option<String>,
+    colouring: Colouring,
+    distance_file: Option<String>,
+    params: RenderParams,
+    threads: usize,
+    strategy: Strategy,
+    output: String,
+}
+
+impl Options {
+
+    // Engine commands to send before rendering
+    fn commands(&self) -> Vec<EngineCommand> {
+        let mut cmds = Vec::new();
+        if self.centre.is_some() || self.width.is_some() {
+            let (re, im) = match self.centre {
+                Some((ref re, ref im)) => (re.clone(), im.clone()),
+                None => (DEFAULT_CENTRE_RE.to_string(), DEFAULT_CENTRE_IM.to_string()),
+            };
+            cmds.push(EngineCommand::SetCentre(re, im, self.width.unwrap_or(DEFAULT_WIDTH)));
+        }
+        cmds.push(EngineCommand::SetFractal(self.fractal.clone()));
+        cmds.push(EngineCommand::SetExponent(self.exponent));
+        cmds.push(EngineCommand::SetVariant(self.variant));
+        if let Some(ref formula) = self.formula {
+            cmds.push(EngineCommand::SetFormula(formula.clone()));
+        }
+        cmds.push(EngineCommand::SetColouring(self.colouring));
+        cmds.push(EngineCommand::ExportDistance(self.distance_file.is_some()));
+        cmds.push(EngineCommand::SetRenderParams(self.params));
+        cmds.push(EngineCommand::SetThreads(self.threads));
+        cmds.push(EngineCommand::SetStrategy(self.strategy));
+        cmds
+    }
+}
+
+fn print_usage() {
+    println!("Usage: mr [options]");
+    println!();
+    println!("Options:");
+    println!("    --centre RE IM    Centre of the view; decimals of any length, for deep zooms");
+    println!("    --width W         Width of the view along the real axis (eg. 1e-100)");
+    println!("    --julia RE IM     Render the Julia set for the constant c = RE + IM*i");
+    println!("    --exponent D      Iterate z^D + c instead of z^2 + c (default 2)");
+    println!("    --variant NAME    classic, burning-ship, tricorn or celtic");
+    println!("    --newton COEFFS   Newton fractal for the polynomial with comma separated");
+    println!("                      coefficients, highest degree first, eg. 1,0,0,-1 or 1,0,2-1.5i");
+    println!("    --formula EXPR    Iterate z = EXPR, in terms of z, c and pixel, eg. \"z^3 + c*sin(z)\"");
+    println!("    --smooth R        Smooth colouring, escaping beyond radius R (eg. 1000)");
+    println!("    --distance T      Draw the boundary in black, fading out over T pixels");
+    println!("    -o, --output F    Save the image to F (default test.ppm); the extension picks");
+    println!("                      the format: ppm, png or jpg");
+    println!("    --distance-out F  Also save the distance to the set of each pixel to F, as PFM");
+    println!("    --iterations N    Iterations before a point counts as inside (default 500)");
+    println!("    --bailout R       Radius at which an orbit escapes (default 2)");
+    println!("    --auto-iterations Raise the iteration limit as the view is zoomed in");
+    println!("    --threads N       Render on N threads (default one per CPU)");
+    println!("    --subdivide       Skip the inside of rectangles with a uniform border");
+    println!("    --supersample S   Anti-alias with several samples per pixel: grid:N for an");
+    println!("                      N x N grid, rgss for a rotated grid of 4, or jitter:N");
+    println!("    --adaptive        Only supersample pixels that differ from their neighbours");
+    println!("    --help            Show this message");
+}
+
+fn next_arg<'a>(args: &'a [String], i: &mut usize, flag: &str) -> Result<&'a str, String> {
+    *i += 1;
+    match args.get(*i) {
+        Some(arg) => Ok(arg),
+        None => Err(format!("missing value for {}", flag)),
+    }
+}
+
+fn parse_f64(s: &str, flag: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(x) => Ok(x),
+        Err(_) => Err(format!("invalid number for {}: {}", flag, s)),
+    }
+}
+
+// Lower case extension of an image file, if it is a format we can save
+fn image_format(filename: &str) -> Option<String> {
+    let extension = Path::new(filename).extension().and_then(|e| e.to_str()).unwrap_or("");
+    match extension.to_lowercase().as_ref() {
+        e @ "ppm" | e @ "png" | e @ "jpg" | e @ "jpeg" => Some(e.to_string()),
+        _ => None,
+    }
+}
+
+// A supersampling pattern such as "grid:3", "rgss" or "jitter:4"
+fn parse_supersampling(s: &str) -> Option<Supersampling> {
+    let mut parts = s.splitn(2, ':');
+    let pattern = parts.next().unwrap_or("");
+    let n = parts.next().map(|n| n.parse::<u32>());
+    match (pattern, n) {
+        ("none", None) => Some(Supersampling::None),
+        ("rgss", None) => Some(Supersampling::RotatedGrid),
+        ("grid", Some(Ok(n))) if n > 0 => Some(Supersampling::Grid(n)),
+        ("jitter", Some(Ok(n))) if n > 0 => Some(Supersampling::Jittered(n)),
+        _ => None,
+    }
+}
+
+// A real or complex number such as "2", "-1.5i" or "0.5-2i"
+fn parse_complex(s: &str) -> Option<Complex> {
+    let s = s.trim();
+    if !s.ends_with('i') {
+        return s.parse::<f64>().ok().map(|re| Complex::new(re, 0.0));
+    }
+
+    // Split at the sign before the imaginary part, skipping exponent signs
+    let body = &s[..s.len()-1];
+    let bytes = body.as_bytes();
+    let split = (1..bytes.len()).rev().find(|&k| {
+        (bytes[k] == b'+' || bytes[k] == b'-') && bytes[k-1] != b'e' && bytes[k-1] != b'E'
+    });
+    let (re, im) = match split {
+        Some(k) => (&body[..k], &body[k..]),
+        None => ("0", body),
+    };
+    let im = match im {
+        "" | "+" => "1",
+        "-" => "-1",
+        im => im,
+    };
+
+    match (re.parse::<f64>(), im.parse::<f64>()) {
+        (Ok(re), Ok(im)) => Some(Complex::new(re, im)),
+        _ => None,
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options { centre: None, width: None, fractal: Fractal::Mandelbrot, exponent: 2.0,
+                               variant: Variant::Classic, formula: None, colouring: Colouring::Banded,
+                               distance_file: None, params: RenderParams::default(),
+                               threads: 0, strategy: Strategy::BruteForce,
+                               output: "test.ppm".to_string() };
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_ref() {
+            "--centre" | "--center" => {
+                let re = try!(next_arg(args, &mut i, "--centre")).to_string();
+                let im = try!(next_arg(args, &mut i, "--centre")).to_string();
+                options.centre = Some((re, im));
+            },
+            "--width" => {
+                let w = try!(next_arg(args, &mut i, "--width"));
+                match w.parse::<f64>() {
+                    Ok(w) if w > 0.0 => options.width = Some(w),
+                    _ => return Err(format!("invalid width: {}", w)),
+                }
+            },
+            "--julia" => {
+                let re = try!(parse_f64(try!(next_arg(args, &mut i, "--julia")), "--julia"));
+                let im = try!(parse_f64(try!(next_arg(args, &mut i, "--julia")), "--julia"));
+                options.fractal = Fractal::Julia { c: Complex::new(re, im) };
+            },
+            "--exponent" => {
+                options.exponent = try!(parse_f64(try!(next_arg(args, &mut i, "--exponent")), "--exponent"));
+            },
+            "--variant" => {
+                options.variant = match try!(next_arg(args, &mut i, "--variant")) {
+                    "classic" => Variant::Classic,
+                    "burning-ship" => Variant::BurningShip,
+                    "tricorn" => Variant::Tricorn,
+                    "celtic" => Variant::Celtic,
+                    name => return Err(format!("unknown variant: {}", name)),
+                };
+            },
+            "--newton" => {
+                let mut coefficients = Vec::new();
+                for s in try!(next_arg(args, &mut i, "--newton")).split(',') {
+                    match parse_complex(s) {
+                        Some(a) => coefficients.push(a),
+                        None => return Err(format!("invalid coefficient for --newton: {}", s)),
+                    }
+                }
+                options.fractal = Fractal::Newton { coefficients: coefficients };
+            },
+            "--formula" => {
+                options.formula = Some(try!(next_arg(args, &mut i, "--formula")).to_string());
+            },
+            "--smooth" => {
+                let r = try!(next_arg(args, &mut i, "--smooth"));
+                match r.parse::<f64>() {
+                    Ok(r) if r >= 2.0 => options.colouring = Colouring::Smooth { bailout: r },
+                    _ => return Err(format!("invalid radius for --smooth, must be at least 2: {}", r)),
+                }
+            },
+            "--distance" => {
+                let t = try!(next_arg(args, &mut i, "--distance"));
+                match t.parse::<f64>() {
+                    Ok(t) if t > 0.0 => options.colouring = Colouring::Distance { thickness: t },
+                    _ => return Err(format!("invalid thickness for --distance: {}", t)),
+                }
+            },
+            "--distance-out" => {
+                options.distance_file = Some(try!(next_arg(args, &mut i, "--distance-out")).to_string());
+            },
+            "--iterations" => {
+                let n = try!(next_arg(args, &mut i, "--iterations"));
+                match n.parse::<u32>() {
+                    Ok(n) if n > 0 => options.params.max_iteration = n,
+                    _ => return Err(format!("invalid iteration count: {}", n)),
+                }
+            },
+            "--bailout" => {
+                let r = try!(next_arg(args, &mut i, "--bailout"));
+                match r.parse::<f64>() {
+                    Ok(r) if r >= 2.0 => options.params.bailout = r,
+                    _ => return Err(format!("invalid bailout radius, must be at least 2: {}", r)),
+                }
+            },
+            "--auto-iterations" => options.params.auto_iterations = true,
+            "--threads" => {
+                let n = try!(next_arg(args, &mut i, "--threads"));
+                match n.parse::<usize>() {
+                    Ok(n) if n > 0 => options.threads = n,
+                    _ => return Err(format!("invalid thread count: {}", n)),
+                }
+            },
+            "--subdivide" => options.strategy = Strategy::Subdivide,
+            "--supersample" => {
+                let pattern = try!(next_arg(args, &mut i, "--supersample"));
+                match parse_supersampling(pattern) {
+                    Some(supersampling) => options.params.supersampling = supersampling,
+                    None => return Err(format!("invalid supersampling pattern: {}", pattern)),
+                }
+            },
+            "--adaptive" => options.params.adaptive = true,
+            "-o" | "--output" => {
+                let f = try!(next_arg(args, &mut i, "--output"));
+                if image_format(f).is_none() {
+                    return Err(format!("unknown image format for --output: {}", f));
+                }
+                options.output = f.to_string();
+            },
+            "--help" | "-h" => {
+                print_usage();
+                process::exit(0);
+            },
+            arg => return Err(format!("unknown option: {}", arg)),
+        }
+        i += 1;
+    }
 
-mod engine;
-mod protocol;
+    Ok(options)
+}
 
 //----------------------------------------------------------------------------
 
@@ -31,6 +299,10 @@ struct CommandLine {
     chan_engine_to_cli: Option<Sender<protocol::EngineStatus>>,
     chan_engine_from_cli: Option<Receiver<protocol::EngineCommand>>,
     image: Option<Vec<u8>>,
+    distance: Option<Vec<f32>>,
+    error: Option<EngineError>,
+    // Generation of the last render asked for
+    generation: u64,
 }
 
 impl CommandLine {
@@ -46,10 +318,13 @@ impl CommandLine {
             chan_engine_to_cli: Some(chan_engine_to_cli),
             chan_engine_from_cli: Some(chan_engine_from_cli),
             image: None,
+            distance: None,
+            error: None,
+            generation: 0,
         }
     }
 
-    pub fn start_engine(&mut self) {
+    pub fn start_engine(&mut self, setup: Vec<EngineCommand>) {
 
         let progress_ch = self.chan_engine_to_cli.take().expect("no engine_to_cli chan");
         let cmd_ch = self.chan_engine_from_cli.take().expect("no engine_from_cli chan");
@@ -62,7 +337,13 @@ impl CommandLine {
         });
 
         match self.chan_cli_to_engine {
-            Some(ref cmd_ch) => cmd_ch.send(EngineCommand::Render(RenderType::FullRender)).unwrap(),
+            Some(ref cmd_ch) => {
+                for cmd in setup {
+                    cmd_ch.send(cmd).unwrap();
+                }
+                cmd_ch.send(EngineCommand::Render(RenderType::FullRender)).unwrap();
+                self.generation += 1;
+            },
             _ => panic!("No chan")
         }
     }
@@ -89,11 +370,39 @@ impl CommandLine {
                                 println!("Processing {}", progress);
                                 false
                             },
-                            EngineStatus::RenderComplete(typ, img) => {
+                            EngineStatus::TileComplete(_, done, total) => {
+                                println!("Tile {}/{}", done, total);
+                                false
+                            },
+                            EngineStatus::PartialFrame(_, pass, passes, _) => {
+                                println!("Pass {}/{}", pass, passes);
+                                false
+                            },
+                            EngineStatus::DistanceField(generation, field) => {
+                                if generation == self.generation {
+                                    self.distance = Some(field);
+                                }
+                                false
+                            },
+                            EngineStatus::Statistics(generation, stats) => {
+                                if generation == self.generation {
+                                    println!("Iterations {}, saved {}", stats.iterations, stats.iterations_saved);
+                                }
+                                false
+                            },
+                            EngineStatus::RenderComplete(generation, _, _) if generation < self.generation => {
+                                println!("Dropping render {}", generation);
+                                false
+                            },
+                            EngineStatus::Cancelled(generation) => {
+                                println!("Render {} cancelled", generation);
+                                false
+                            },
+                            EngineStatus::RenderComplete(_, typ, img) => {
                                 println!("Render Complete!");
                                 self.image = Some(img);
                                 match typ {
-                                    RenderType::FullRender => {
+                                    RenderType::FullRender | RenderType::ProgressiveRender => {
                                         println!("fullRender {} {}", self.width, self.height);
                                     },
                                     RenderType::PreviewRender => {
@@ -102,9 +411,10 @@ impl CommandLine {
                                 };
                                 true
                             },
-                            EngineStatus::Error(code) => {
-                                println!("Error {}", code);
-                                false
+                            EngineStatus::Error(e) => {
+                                println!("Error: {}", e);
+                                self.error = Some(e);
+                                true
                             },
                         },
                     _ => false,
@@ -114,14 +424,49 @@ impl CommandLine {
         }
     }
 
-    pub fn save_as_ppm(&self, filename: &str) -> std::io::Result<()> {
+    // In the format given by the file's extension
+    pub fn save_image(&self, filename: &str) -> std::io::Result<()> {
         match self.image {
             Some(ref img) => {
                 println!("Saving {}", filename);
+                match image_format(filename) {
+                    Some(ref e) if e == "ppm" => self.save_as_ppm(filename),
+                    _ => image::save_buffer(filename, img, self.width, self.height, image::ColorType::RGB(8)),
+                }
+            },
+            None => Err(Error::new(ErrorKind::NotFound, "file")),
+        }
+    }
+
+    pub fn save_as_ppm(&self, filename: &str) -> std::io::Result<()> {
+        match self.image {
+            Some(ref img) => {
                 let mut file = try!(File::create(filename));
                 try!(file.write_all("P6\n".as_bytes()));
                 try!(file.write_all(format!("{} {}\n255\n", self.width, self.height).as_bytes()));
-                try!(file.write_all(&img));
+                try!(file.write_all(img));
+                Ok(())
+            },
+            None => Err(Error::new(ErrorKind::NotFound, "file")),
+        }
+    }
+
+    // Greyscale PFM, which stores the bottom row first
+    pub fn save_as_pfm(&self, filename: &str) -> std::io::Result<()> {
+        match self.distance {
+            Some(ref field) => {
+                println!("Saving {}", filename);
+                let mut file = try!(File::create(filename));
+                try!(file.write_all("Pf\n".as_bytes()));
+                try!(file.write_all(format!("{} {}\n-1.0\n", self.width, self.height).as_bytes()));
+                let mut bytes = Vec::with_capacity(field.len() * 4);
+                for row in field.chunks(self.width as usize).rev() {
+                    for &d in row {
+                        let bits = d.to_bits();
+                        bytes.extend_from_slice(&[bits as u8, (bits >> 8) as u8, (bits >> 16) as u8, (bits >> 24) as u8]);
+                    }
+                }
+                try!(file.write_all(&bytes));
                 Ok(())
             },
             None => Err(Error::new(ErrorKind::NotFound, "file")),
@@ -131,15 +476,61 @@ impl CommandLine {
 
 fn main() {
 
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(msg) => {
+            println!("mr: {}", msg);
+            print_usage();
+            process::exit(1);
+        }
+    };
+
     let mut cli = CommandLine::new(640, 640);
 
-    cli.start_engine();
+    cli.start_engine(options.commands());
 
+    let mut saved = true;
     loop {
-        if cli.handle_update() == true {
-            cli.save_as_ppm("test.ppm").unwrap();
+        if cli.handle_update() {
+            if cli.error.is_none() {
+                let mut files = vec![(options.output.as_str(), cli.save_image(&options.output))];
+                if let Some(ref filename) = options.distance_file {
+                    files.push((filename, cli.save_as_pfm(filename)));
+                }
+                for (filename, result) in files {
+                    if let Err(e) = result {
+                        println!("mr: could not save {}: {}", filename, e);
+                        saved = false;
+                    }
+                }
+            }
             cli.stop_engine();
             break;
         }
     }
+
+    if cli.error.is_some() || !saved {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::image_format;
+
+    #[test]
+    fn image_format_follows_the_extension() {
+        assert_eq!(image_format("out.ppm"), Some("ppm".to_string()));
+        assert_eq!(image_format("out.png"), Some("png".to_string()));
+        assert_eq!(image_format("out.jpg"), Some("jpg".to_string()));
+        assert_eq!(image_format("out.jpeg"), Some("jpeg".to_string()));
+        assert_eq!(image_format("dir.png/out.PNG"), Some("png".to_string()));
+        assert_eq!(image_format("OUT.JPEG"), Some("jpeg".to_string()));
+        assert_eq!(image_format("out.bmp"), None);
+        assert_eq!(image_format("out.tiff"), None);
+        assert_eq!(image_format("out"), None);
+        assert_eq!(image_format("png"), None);
+    }
 }
@@ -0,0 +1,109 @@
+//============================================================================
+//
+// A simple Mandelbrot image generator in Rust
+//
+// Newton's method root-finding fractals
+//
+// Copyright (c) 2014 Gavin Baker <gavinb@antonym.org>
+// Published under the MIT license
+//
+//============================================================================
+
+use std::vec::Vec;
+
+use complex::Complex;
+
+//----------------------------------------------------------------------------
+
+// Two points this close (squared) are taken to be the same root
+static ROOT_TOLERANCE: f64 = 1.0e-12;
+
+// Iterations of Durand-Kerner used to find the roots up front
+static ROOT_ITERATIONS: u32 = 1000;
+
+//----------------------------------------------------------------------------
+
+pub struct Polynomial {
+    // Highest degree first
+    coefficients: Vec<Complex>,
+}
+
+impl Polynomial {
+
+    pub fn new(coefficients: &[Complex]) -> Polynomial {
+        let zero = Complex::zero();
+        let lead = coefficients.iter().position(|&a| a != zero).unwrap_or(coefficients.len());
+        Polynomial { coefficients: coefficients[lead..].to_vec() }
+    }
+
+    pub fn degree(&self) -> usize {
+        if self.coefficients.is_empty() { 0 } else { self.coefficients.len() - 1 }
+    }
+
+    // p(z) and p'(z) together, by Horner's rule
+    pub fn eval(&self, z: Complex) -> (Complex, Complex) {
+        let mut p = Complex::zero();
+        let mut dp = Complex::zero();
+        for &a in self.coefficients.iter() {
+            dp = dp * z + p;
+            p = p * z + a;
+        }
+        (p, dp)
+    }
+
+    // All the roots, found simultaneously with the Durand-Kerner method
+    pub fn roots(&self) -> Vec<Complex> {
+        let n = self.degree();
+        if n == 0 {
+            return Vec::new();
+        }
+
+        let lead = self.coefficients[0];
+        let monic = Polynomial { coefficients: self.coefficients.iter().map(|&a| a / lead).collect() };
+
+        let seed = Complex::new(0.4, 0.9);
+        let mut roots: Vec<Complex> = (0..n).map(|k| seed.powi(k as u32)).collect();
+        for _ in 0..ROOT_ITERATIONS {
+            let mut change = 0.0f64;
+            for i in 0..n {
+                let mut denom = Complex::new(1.0, 0.0);
+                for j in 0..n {
+                    if i != j {
+                        denom = denom * (roots[i] - roots[j]);
+                    }
+                }
+                let (p, _) = monic.eval(roots[i]);
+                let step = p / denom;
+                roots[i] = roots[i] - step;
+                change = change.max(step.norm_sqr());
+            }
+            if change < ROOT_TOLERANCE * ROOT_TOLERANCE {
+                break;
+            }
+        }
+        roots
+    }
+
+    // Run Newton's method from z, returning the index into `roots` of the
+    // root it settles on, if any, and the number of steps taken
+    pub fn newton(&self, roots: &[Complex], mut z: Complex, max_iteration: u32) -> (Option<usize>, u32) {
+        let mut steps = 0;
+        while steps < max_iteration {
+            for (k, &root) in roots.iter().enumerate() {
+                if (z - root).norm_sqr() < ROOT_TOLERANCE {
+                    return (Some(k), steps);
+                }
+            }
+
+            let (p, dp) = self.eval(z);
+            if dp.norm_sqr() == 0.0 {
+                break;
+            }
+            z = z - p / dp;
+            steps += 1;
+        }
+        (None, steps)
+    }
+}
+
+//----------------------------------------------------------------------------
@@ -0,0 +1,330 @@
+//============================================================================
+//
+// A simple Mandelbrot image generator in Rust
+//
+// Perturbation theory for deep zooms
+//
+// Copyright (c) 2014 Gavin Baker <gavinb@antonym.org>
+// Published under the MIT license
+//
+//============================================================================
+
+// Beyond about 1e-13 the pixel spacing is lost in the rounding of c itself,
+// so iterating each pixel in f64 just produces blocks.  Instead we iterate
+// a single reference point C in high precision, and each pixel c = C + dc
+// as a small delta from that orbit:
+//
+//     z[n] = Z[n] + dz[n]
+//     dz[n+1] = 2*Z[n]*dz[n] + dz[n]^2 + dc
+//
+// The deltas stay small, so f64 is ample to carry them.
+//
+// Where the pixel's orbit passes much closer to zero than the reference
+// does, the delta is no longer small next to z and the result is garbage
+// ("glitches").  These pixels are detected with Pauldelbrot's criterion
+// |Z+dz|^2 < tolerance*|Z|^2 and recomputed against a new reference
+// picked from inside the glitched blob.
+//
+// Early on, every pixel's delta is very nearly a polynomial in dc, so the
+// first few thousand iterations can be skipped for the whole frame:
+//
+//     dz[n] ~= A[n]*dc + B[n]*dc^2 + C[n]*dc^3
+//     A[n+1] = 2*Z[n]*A[n] + 1
+//     B[n+1] = 2*Z[n]*B[n] + A[n]^2
+//     C[n+1] = 2*Z[n]*C[n] + 2*A[n]*B[n]
+//
+// The series is followed while the cubic term stays negligible next to
+// both lower terms, and then checked against fully iterated probe points
+// across the frame.  The error allowed is measured against |A|*pixel_size,
+// how far apart the deltas of neighbouring pixels lie, rather than against
+// dz: once A has been shrunk by a pass near zero, dz is mostly the B term
+// and a small relative error can still move a pixel by a good part of its
+// width.
+
+use std::vec::Vec;
+
+use bignum::BigFixed;
+use complex::Complex;
+
+//----------------------------------------------------------------------------
+
+// Relative size of z against the reference below which a pixel is glitched
+static GLITCH_TOLERANCE: f64 = 1.0e-6;
+
+// Largest ratio of the cubic to either lower series term at the frame edge
+static SERIES_TOLERANCE: f64 = 1.0e-3;
+
+// Largest error of the series at a probe point, in units of |A|*pixel_size.
+// Near minibrots a pixel nudged by a ten billionth of its width can change
+// its count, so this is kept near the rounding of the iteration itself.
+static PROBE_TOLERANCE: f64 = 1.0e-12;
+
+pub enum Perturbed {
+    // Escape iteration, with z and dz/dc where it stopped
+    Done(u32, Complex, Complex),
+    // Precision lost, with |z|^2 at that point; the smallest values in a
+    // glitched blob lie closest to the nucleus causing it
+    Glitched(f64),
+}
+
+//----------------------------------------------------------------------------
+
+// Polynomial for the delta after `skip` iterations
+pub struct SeriesApproximation {
+    skip: u32,
+    a: Complex,
+    b: Complex,
+    c: Complex,
+}
+
+impl SeriesApproximation {
+
+    // Skips nothing; the delta starts from zero
+    pub fn none() -> SeriesApproximation {
+        SeriesApproximation { skip: 0, a: Complex::zero(), b: Complex::zero(), c: Complex::zero() }
+    }
+
+    pub fn skip(&self) -> u32 {
+        self.skip
+    }
+
+    fn approximate(&self, dc: Complex) -> Complex {
+        ((self.c * dc + self.b) * dc + self.a) * dc
+    }
+
+    // d/dc of the approximation, for the derivative of the pixel's orbit
+    fn derivative(&self, dc: Complex) -> Complex {
+        (self.c.scale(3.0) * dc + self.b.scale(2.0)) * dc + self.a
+    }
+}
+
+//----------------------------------------------------------------------------
+
+pub struct ReferenceOrbit {
+    orbit: Vec<Complex>,
+    // Square of the bailout radius the orbit was computed for
+    escape: f64,
+}
+
+impl ReferenceOrbit {
+
+    // Iterate the reference point (c_re, c_im) at its own precision until
+    // it leaves the bailout radius, keeping each point rounded to f64
+    pub fn compute(c_re: &BigFixed, c_im: &BigFixed, max_iteration: u32, bailout: f64) -> ReferenceOrbit {
+        let escape = bailout*bailout;
+        let prec = if c_re.precision() > c_im.precision() { c_re.precision() } else { c_im.precision() };
+        let mut zr = BigFixed::zero(prec);
+        let mut zi = BigFixed::zero(prec);
+        let mut orbit = Vec::with_capacity(max_iteration as usize + 1);
+
+        loop {
+            let z = Complex::new(zr.to_f64(), zi.to_f64());
+            orbit.push(z);
+            if orbit.len() > max_iteration as usize || z.norm_sqr() >= escape {
+                break;
+            }
+
+            let zr2 = &zr * &zr;
+            let zi2 = &zi * &zi;
+            let zri = &zr * &zi;
+            zi = &(&zri + &zri) + c_im;
+            zr = &(&zr2 - &zi2) + c_re;
+        }
+
+        ReferenceOrbit { orbit: orbit, escape: escape }
+    }
+
+    // Number of iterations before the reference itself escaped
+    pub fn len(&self) -> usize {
+        self.orbit.len() - 1
+    }
+
+    // True when the reference escaped straight away and has no orbit to follow
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Series coefficients for a frame whose deltas are bounded by the
+    // probe points, skipping as many iterations as both checks allow
+    pub fn series(&self, probes: &[Complex], pixel_size: f64, max_iteration: u32) -> SeriesApproximation {
+        let radius = probes.iter().fold(0.0, |r: f64, p| r.max(p.norm()));
+        let end = if (max_iteration as usize) < self.len() { max_iteration as usize } else { self.len() };
+
+        // Follow the series while the cubic term stays negligible; each
+        // term here is its size at the frame edge, over the radius
+        let mut terms = vec![(Complex::zero(), Complex::zero(), Complex::zero())];
+        for n in 0..end {
+            let (a, b, c) = terms[n];
+            let z2 = self.orbit[n].scale(2.0);
+            let next = (z2 * a + Complex::new(1.0, 0.0),
+                        z2 * b + a * a,
+                        z2 * c + (a * b).scale(2.0));
+            let linear = next.0.norm();
+            let quadratic = next.1.norm() * radius;
+            let cubic = next.2.norm() * radius * radius;
+            if cubic > SERIES_TOLERANCE * quadratic || cubic > SERIES_TOLERANCE * linear {
+                break;
+            }
+            terms.push(next);
+        }
+
+        // Back off to the last iteration where every probe agrees
+        let mut skip = terms.len() - 1;
+        for &dc in probes {
+            let mut dz = Complex::zero();
+            for n in 0..skip {
+                let zn = self.orbit[n];
+                let z = zn + dz;
+                if z.norm_sqr() >= self.escape || z.norm_sqr() < GLITCH_TOLERANCE * zn.norm_sqr() {
+                    skip = n;
+                    break;
+                }
+                dz = (zn.scale(2.0) + dz) * dz + dc;
+
+                let (a, b, c) = terms[n+1];
+                let approx = ((c * dc + b) * dc + a) * dc;
+                if (approx - dz).norm() > PROBE_TOLERANCE * a.norm() * pixel_size {
+                    skip = n;
+                    break;
+                }
+            }
+        }
+
+        let (a, b, c) = terms[skip];
+        SeriesApproximation { skip: skip as u32, a: a, b: b, c: c }
+    }
+
+    // Escape iteration of the pixel at offset dc from the reference, or
+    // Glitched if it loses precision or outlives the reference orbit
+    pub fn iterate(&self, dc: Complex, series: &SeriesApproximation, max_iteration: u32) -> Perturbed {
+        let last = self.len();
+        let mut dz = series.approximate(dc);
+        let mut der = series.derivative(dc);
+        let mut iteration = series.skip;
+
+        while iteration < max_iteration {
+            let n = iteration as usize;
+            let zn = self.orbit[n];
+            let z = zn + dz;
+            let size = z.norm_sqr();
+            if size >= self.escape {
+                return Perturbed::Done(iteration, z, der);
+            }
+            if n == last || size < GLITCH_TOLERANCE * zn.norm_sqr() {
+                return Perturbed::Glitched(size);
+            }
+            der = (z * der).scale(2.0) + Complex::new(1.0, 0.0);
+            dz = (zn.scale(2.0) + dz) * dz + dc;
+            iteration += 1;
+        }
+
+        Perturbed::Done(iteration, self.orbit[iteration as usize] + dz, der)
+    }
+
+    // As iterate, without glitch detection.  If the reference escapes
+    // first, carry on directly from the absolute orbit point, using c as
+    // the pixel's approximate position.
+    pub fn iterate_unchecked(&self, dc: Complex, c: Complex, series: &SeriesApproximation,
+                             max_iteration: u32) -> (u32, Complex, Complex) {
+        let last = self.len();
+        let mut dz = series.approximate(dc);
+        let mut der = series.derivative(dc);
+        let mut iteration = series.skip;
+
+        while iteration < max_iteration {
+            let n = iteration as usize;
+            let zn = self.orbit[n];
+            if n == last {
+                let mut z = zn + dz;
+                while z.norm_sqr() < self.escape && iteration < max_iteration {
+                    der = (z * der).scale(2.0) + Complex::new(1.0, 0.0);
+                    z = z*z + c;
+                    iteration += 1;
+                }
+                return (iteration, z, der);
+            }
+            let z = zn + dz;
+            if z.norm_sqr() >= self.escape {
+                break;
+            }
+            der = (z * der).scale(2.0) + Complex::new(1.0, 0.0);
+            dz = (zn.scale(2.0) + dz) * dz + dc;
+            iteration += 1;
+        }
+
+        (iteration, self.orbit[iteration as usize] + dz, der)
+    }
+}
+
+//----------------------------------------------------------------------------
+
+// Flood fill the blob of glitched pixels containing `seed`, and return the
+// pixel in it that makes the best new reference, along with the whole blob
+pub fn blob_reference(glitched: &[Option<f64>], width: u32, height: u32, seed: usize) -> (usize, Vec<usize>) {
+    let w = width as usize;
+    let h = height as usize;
+    let mut visited = vec![false; glitched.len()];
+    let mut stack = vec![seed];
+    let mut best = seed;
+    let mut best_size = f64::MAX;
+    let mut blob = Vec::new();
+    visited[seed] = true;
+
+    while let Some(i) = stack.pop() {
+        blob.push(i);
+        if let Some(size) = glitched[i] {
+            if size < best_size {
+                best = i;
+                best_size = size;
+            }
+        }
+
+        let (x, y) = (i % w, i / w);
+        let mut neighbours = Vec::with_capacity(4);
+        if x > 0 { neighbours.push(i - 1); }
+        if x + 1 < w { neighbours.push(i + 1); }
+        if y > 0 { neighbours.push(i - w); }
+        if y + 1 < h { neighbours.push(i + w); }
+
+        for j in neighbours {
+            if !visited[j] && glitched[j].is_some() {
+                visited[j] = true;
+                stack.push(j);
+            }
+        }
+    }
+
+    (best, blob)
+}
+
+//----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::blob_reference;
+
+    // Three blobs in a 5 x 4 mask.  The first two touch only at a corner,
+    // and the third follows the second in memory but on the next row.
+    //
+    //     .    0.5  0.2  .    .
+    //     .    0.3  .    0.6  0.1
+    //     0.001 .   .    0.05 0.4
+    //     0.01 .    .    .    .
+    #[test]
+    fn blob_reference_fills_one_blob() {
+        let mask = vec![None, Some(0.5), Some(0.2), None, None,
+                        None, Some(0.3), None, Some(0.6), Some(0.1),
+                        Some(0.001), None, None, Some(0.05), Some(0.4),
+                        Some(0.01), None, None, None, None];
+        let blob = |seed| {
+            let (best, mut blob) = blob_reference(&mask, 5, 4, seed);
+            blob.sort();
+            (best, blob)
+        };
+
+        assert_eq!(blob(6), (2, vec![1, 2, 6]));
+        assert_eq!(blob(14), (13, vec![8, 9, 13, 14]));
+        assert_eq!(blob(15), (10, vec![10, 15]));
+    }
+}
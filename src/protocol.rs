@@ -11,8 +11,12 @@
 
 #![allow(dead_code)]
 
+use std::fmt;
 use std::vec::Vec;
 
+use complex::Complex;
+use expr::ParseError;
+
 //----------------------------------------------------------------------------
 
 pub static PREVIEW_WIDTH: i32 = 256;
@@ -24,29 +28,204 @@ pub static PREVIEW_HEIGHT: i32 = 256;
 pub enum RenderType {
     PreviewRender,
     FullRender,
+    // Full size, refined over several passes that each send a PartialFrame
+    ProgressiveRender,
+}
+
+//----------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub enum Fractal {
+    // z0 = 0, c = pixel
+    Mandelbrot,
+    // z0 = pixel, c fixed
+    Julia { c: Complex },
+    // Newton's method on a polynomial, coefficients highest degree first
+    Newton { coefficients: Vec<Complex> },
+}
+
+//----------------------------------------------------------------------------
+
+// Folding applied to z around the power in z^d + c
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    Classic,
+    // (|re z| + i|im z|)^d, the Burning Ship
+    BurningShip,
+    // conj(z)^d, the Tricorn or Mandelbar
+    Tricorn,
+    // |re z^d| + i im z^d
+    Celtic,
+}
+
+//----------------------------------------------------------------------------
+
+// How escape counts are mapped onto the palette
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Colouring {
+    // One palette entry per whole iteration
+    Banded,
+    // Fractional escape counts, blended between palette entries; the
+    // larger the bailout radius, the smoother the result
+    Smooth { bailout: f64 },
+    // Black on white by estimated distance to the set, so that filaments
+    // stay visible; thickness is in pixels
+    Distance { thickness: f64 },
+}
+
+//----------------------------------------------------------------------------
+
+// How the pixels of a frame are worked out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    // Iterate every pixel
+    BruteForce,
+    // Mariani-Silver subdivision: a rectangle whose border pixels all have
+    // the same iteration count is filled in without iterating inside it,
+    // otherwise it is split in two and each half checked the same way
+    Subdivide,
+}
+
+//----------------------------------------------------------------------------
+
+// Samples taken in each pixel of a full render, to smooth out aliasing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Supersampling {
+    // One sample per pixel
+    None,
+    // An n x n grid of samples
+    Grid(u32),
+    // Four samples on a grid turned to catch near horizontal and vertical
+    // edges, as in RGSS
+    RotatedGrid,
+    // One sample somewhere random in each cell of an n x n grid; the same
+    // pixel always gets the same samples
+    Jittered(u32),
+}
+
+//----------------------------------------------------------------------------
+
+// Limits on the iteration of each pixel, and how many samples make it up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderParams {
+    // Iterations before a point is taken to be inside the set
+    pub max_iteration: u32,
+    // Radius beyond which an orbit has escaped; smooth and distance
+    // colouring may raise it
+    pub bailout: f64,
+    // Raise max_iteration the further the view is zoomed in
+    pub auto_iterations: bool,
+    // For FullRender only; the samples are averaged in linear light
+    pub supersampling: Supersampling,
+    // Supersample only the pixels that stand out from their neighbours
+    pub adaptive: bool,
+}
+
+impl Default for RenderParams {
+    fn default() -> RenderParams {
+        RenderParams { max_iteration: 500, bailout: 2.0, auto_iterations: false,
+                       supersampling: Supersampling::None, adaptive: false }
+    }
+}
+
+//----------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub enum EngineError {
+    // A SetCentre coordinate that is not a decimal number
+    InvalidCoordinate(String),
+    // A Newton polynomial without any roots
+    InvalidPolynomial,
+    // A SetFormula expression that doesn't parse
+    Formula(ParseError),
+    // Distance estimation with a formula that has no derivative
+    NoDerivative,
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EngineError::InvalidCoordinate(ref s) => write!(f, "invalid coordinate '{}'", s),
+            EngineError::InvalidPolynomial => write!(f, "the polynomial must be of degree 1 or more"),
+            EngineError::Formula(ref e) => write!(f, "invalid formula at {}", e),
+            EngineError::NoDerivative => write!(f, "the formula has no derivative to estimate distances with"),
+        }
+    }
+}
+
+//----------------------------------------------------------------------------
+
+// Work done on one frame
+#[derive(Debug, Clone, Copy)]
+pub struct RenderStats {
+    // Iterations actually computed
+    pub iterations: u64,
+    // Iterations skipped for points found to be inside the set early
+    pub iterations_saved: u64,
 }
 
 //----------------------------------------------------------------------------
 
+// Results carry the generation of the render they came from: each Render
+// command starts a new one, counting from 1 in the order they were sent, so
+// anything from before the latest Render is out of date.
 #[derive(Debug)]
 pub enum EngineStatus {
     Startup,
     Processing(u32),
-    RenderComplete(RenderType, Vec<u8>),
-    Error(u32)
+    // Generation, and tiles finished so far, out of the total for the frame
+    TileComplete(u64, u32, u32),
+    // Generation, pass number, out of the total, of a progressive render,
+    // and the full size image so far with the pixels still to come filled
+    // in blocks.  Pass 0 is the previous frame rescaled after a zoom, sent
+    // before the first pass of a progressive or full size render: once
+    // with the lines between the reused ones copied from their neighbours,
+    // and again once those lines have been worked out.
+    PartialFrame(u64, u32, u32, Vec<u8>),
+    // Generation, and estimated distance to the set of each pixel, in
+    // pixels, sent just before RenderComplete when exporting distances,
+    // except for progressive renders
+    DistanceField(u64, Vec<f32>),
+    // Generation, and the work done, sent before RenderComplete, and before
+    // each PartialFrame that took any iterating
+    Statistics(u64, RenderStats),
+    RenderComplete(u64, RenderType, Vec<u8>),
+    // The render of this generation was dropped for a newer command
+    Cancelled(u64),
+    Error(EngineError)
 }
 
 //----------------------------------------------------------------------------
 
+// Commands that queue up while the engine is busy are taken in together:
+// moves of the view are applied in the order sent, and a Render with a
+// later Render or a Shutdown behind it is skipped, as Cancelled, so a burst
+// of keypresses ends in a single render of wherever it finished.
 #[derive(Debug)]
 pub enum EngineCommand {
-    UpdateRegion(f32, f32, f32, f32),
+    UpdateRegion(f64, f64, f64, f64),
+    // Centre re, im as decimal strings of any precision, and width
+    SetCentre(String, String, f64),
     ZoomIn,
     ZoomOut,
     PanLeft,
     PanRight,
     PanUp,
     PanDown,
+    SetFractal(Fractal),
+    // Iterate z^d + c; d need not be a whole number, or positive
+    SetExponent(f64),
+    SetVariant(Variant),
+    // Iterate z = an expression, eg. "z^3 + c*sin(z)"; empty to go back
+    // to the built in formulas
+    SetFormula(String),
+    SetColouring(Colouring),
+    // Send a DistanceField along with each render
+    ExportDistance(bool),
+    SetRenderParams(RenderParams),
+    // Worker threads to render with, or 0 for one per CPU
+    SetThreads(usize),
+    SetStrategy(Strategy),
     Render(RenderType),
     Shutdown,
 }
@@ -30,7 +30,7 @@ uniform sampler2D tex;
 
 void main()
 {
-    outColor = texture(tex, v_texcoord) * vec4(8,8,8,1);
+    outColor = texture(tex, v_texcoord);
 }
 ";
 
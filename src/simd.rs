@@ -0,0 +1,320 @@
+//============================================================================
+//
+// A simple Mandelbrot image generator in Rust
+//
+// Escape-time kernel for z^2 + c, several pixels at a time
+//
+// Copyright (c) 2014 Gavin Baker <gavinb@antonym.org>
+// Published under the MIT license
+//
+//============================================================================
+
+// Each lane is one pixel.  All lanes take a step together, and a mask
+// records which of them are still going.  As soon as any lane stops, its
+// result is stored and the next pixel of the row takes its place, so no
+// lane sits idle waiting for a slow neighbour.  The arithmetic is written
+// exactly as in the scalar loop, in the same order, so every pixel comes out
+// bit for bit the same as it would on its own.  Counts are kept as f64 so
+// that they fit the same vector registers.
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::vec::Vec;
+
+// Points iterated at once, one per f64 in an AVX2 register, as the arrays
+// below are sized
+static LANES: usize = 4;
+
+//----------------------------------------------------------------------------
+
+// Where the orbit of one point ended up
+#[derive(Debug, Clone, Copy)]
+pub struct Orbit {
+    pub iteration: u32,
+    pub z_re: f64,
+    pub z_im: f64,
+    // Iterations skipped by finding the point inside the set early
+    pub saved: u64,
+}
+
+// The orbits in flight, one per lane
+struct Lanes {
+    // Index of the point in each lane
+    point: [usize; 4],
+    active: [bool; 4],
+    zr: [f64; 4],
+    zi: [f64; 4],
+    cr: [f64; 4],
+    ci: [f64; 4],
+    n: [f64; 4],
+    // Brent's cycle detection, as in the scalar loop
+    cycle_re: [f64; 4],
+    cycle_im: [f64; 4],
+    cycle_length: [f64; 4],
+    steps: [f64; 4],
+    // Why each lane stopped, after a call to step
+    escaped: [bool; 4],
+    periodic: [bool; 4],
+    done: [bool; 4],
+}
+
+// Ways of stepping the lanes, which all give the same results
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kernel {
+    // One lane after another, on any CPU
+    Scalar,
+    // All lanes together in AVX2 registers
+    Avx2,
+}
+
+// Escape radius squared, period tolerance and iteration limit for a step
+#[derive(Clone, Copy)]
+struct Limits {
+    escape: f64,
+    period_tolerance: f64,
+    max_iteration: f64,
+}
+
+// True if the CPU can run the vector kernel
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn available() -> bool {
+    is_x86_feature_detected!("avx2")
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn available() -> bool {
+    false
+}
+
+// The kernels the CPU can run
+pub fn kernels() -> Vec<Kernel> {
+    if available() { vec![Kernel::Scalar, Kernel::Avx2] } else { vec![Kernel::Scalar] }
+}
+
+// Iterate z^2 + c from each z0 with its c, except for the points marked
+// `inside`, which are already known not to escape.  Orbits stop once |z|
+// reaches the bailout radius, when they come back round to an earlier
+// point within `period_tolerance`, or after max_iteration steps.
+pub fn iterate(z0: &[(f64, f64)], c: &[(f64, f64)], inside: &[bool],
+               bailout: f64, period_tolerance: f64, max_iteration: u32) -> Vec<Orbit> {
+    let kernel = if available() { Kernel::Avx2 } else { Kernel::Scalar };
+    iterate_with(kernel, z0, c, inside, bailout, period_tolerance, max_iteration)
+}
+
+// As iterate, on a kernel of the caller's choosing, one of kernels()
+pub fn iterate_with(kernel: Kernel, z0: &[(f64, f64)], c: &[(f64, f64)], inside: &[bool],
+                    bailout: f64, period_tolerance: f64, max_iteration: u32) -> Vec<Orbit> {
+    let limits = Limits {
+        escape: bailout*bailout,
+        period_tolerance: period_tolerance,
+        max_iteration: max_iteration as f64,
+    };
+
+    match kernel {
+        Kernel::Scalar => run(z0, c, inside, max_iteration, |lanes| step_lanes(lanes, limits)),
+        Kernel::Avx2 => run_avx2(z0, c, inside, max_iteration, limits),
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn run_avx2(z0: &[(f64, f64)], c: &[(f64, f64)], inside: &[bool], max_iteration: u32,
+            limits: Limits) -> Vec<Orbit> {
+    assert!(available(), "this CPU has no AVX2");
+    run(z0, c, inside, max_iteration, |lanes| unsafe { step_avx2(lanes, limits) })
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn run_avx2(_: &[(f64, f64)], _: &[(f64, f64)], _: &[bool], _: u32, _: Limits) -> Vec<Orbit> {
+    panic!("AVX2 is only for x86");
+}
+
+// Feed the points through the lanes, calling `step` to advance them until
+// one or more stop
+fn run<F>(z0: &[(f64, f64)], c: &[(f64, f64)], inside: &[bool], max_iteration: u32,
+          mut step: F) -> Vec<Orbit> where F: FnMut(&mut Lanes) {
+
+    let npoints = z0.len();
+    let mut orbits = vec![Orbit { iteration: 0, z_re: 0.0, z_im: 0.0, saved: 0 }; npoints];
+    let mut next = 0;
+    let mut lanes = Lanes {
+        point: [0; 4],
+        active: [false; 4],
+        zr: [0.0; 4],
+        zi: [0.0; 4],
+        cr: [0.0; 4],
+        ci: [0.0; 4],
+        n: [0.0; 4],
+        cycle_re: [0.0; 4],
+        cycle_im: [0.0; 4],
+        cycle_length: [1.0; 4],
+        steps: [0.0; 4],
+        escaped: [false; 4],
+        periodic: [false; 4],
+        done: [false; 4],
+    };
+
+    loop {
+        // Start the next points in any idle lanes
+        for l in 0..LANES {
+            if lanes.active[l] {
+                continue;
+            }
+            while next < npoints {
+                let p = next;
+                next += 1;
+                if inside[p] || max_iteration == 0 {
+                    let (iteration, saved) = if inside[p] { (max_iteration, max_iteration as u64) } else { (0, 0) };
+                    orbits[p] = Orbit { iteration: iteration, z_re: z0[p].0, z_im: z0[p].1, saved: saved };
+                    continue;
+                }
+                lanes.point[l] = p;
+                lanes.active[l] = true;
+                lanes.zr[l] = z0[p].0;
+                lanes.zi[l] = z0[p].1;
+                lanes.cr[l] = c[p].0;
+                lanes.ci[l] = c[p].1;
+                lanes.n[l] = 0.0;
+                lanes.cycle_re[l] = z0[p].0;
+                lanes.cycle_im[l] = z0[p].1;
+                lanes.cycle_length[l] = 1.0;
+                lanes.steps[l] = 0.0;
+                break;
+            }
+        }
+        if !lanes.active.iter().any(|&a| a) {
+            break;
+        }
+
+        step(&mut lanes);
+
+        // Store the orbits that have finished
+        for l in 0..LANES {
+            if !lanes.done[l] {
+                continue;
+            }
+            let n = lanes.n[l] as u32;
+            let iteration = if lanes.escaped[l] { n } else { max_iteration };
+            let saved = if lanes.periodic[l] { (max_iteration - n) as u64 } else { 0 };
+            orbits[lanes.point[l]] = Orbit { iteration: iteration, z_re: lanes.zr[l], z_im: lanes.zi[l], saved: saved };
+            lanes.active[l] = false;
+        }
+    }
+
+    orbits
+}
+
+// Step every lane until at least one stops, one lane at a time
+fn step_lanes(lanes: &mut Lanes, limits: Limits) {
+    loop {
+        let mut stopped = false;
+        for l in 0..LANES {
+            let x = lanes.zr[l];
+            let y = lanes.zi[l];
+            let escaped = lanes.active[l] && x*x + y*y >= limits.escape;
+            let live = lanes.active[l] && !escaped;
+
+            if live {
+                lanes.zr[l] = (x*x - y*y) + lanes.cr[l];
+                lanes.zi[l] = (x*y + y*x) + lanes.ci[l];
+                lanes.n[l] += 1.0;
+            }
+
+            let dx = lanes.zr[l] - lanes.cycle_re[l];
+            let dy = lanes.zi[l] - lanes.cycle_im[l];
+            let periodic = live && dx*dx + dy*dy < limits.period_tolerance;
+
+            lanes.steps[l] += 1.0;
+            if lanes.steps[l] == lanes.cycle_length[l] {
+                lanes.cycle_re[l] = lanes.zr[l];
+                lanes.cycle_im[l] = lanes.zi[l];
+                lanes.cycle_length[l] *= 2.0;
+                lanes.steps[l] = 0.0;
+            }
+
+            lanes.escaped[l] = escaped;
+            lanes.periodic[l] = periodic;
+            lanes.done[l] = lanes.active[l] && (escaped || periodic || lanes.n[l] == limits.max_iteration);
+            stopped |= lanes.done[l];
+        }
+        if stopped {
+            break;
+        }
+    }
+}
+
+// As step_lanes, with all four lanes in AVX registers
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn step_avx2(lanes: &mut Lanes, limits: Limits) {
+    let mut bits = [0.0; 4];
+    for (bit, &active) in bits.iter_mut().zip(lanes.active.iter()) {
+        *bit = if active { f64::from_bits(!0) } else { 0.0 };
+    }
+    let active = _mm256_loadu_pd(bits.as_ptr());
+
+    let mut zr = _mm256_loadu_pd(lanes.zr.as_ptr());
+    let mut zi = _mm256_loadu_pd(lanes.zi.as_ptr());
+    let cr = _mm256_loadu_pd(lanes.cr.as_ptr());
+    let ci = _mm256_loadu_pd(lanes.ci.as_ptr());
+    let mut n = _mm256_loadu_pd(lanes.n.as_ptr());
+    let mut cycle_re = _mm256_loadu_pd(lanes.cycle_re.as_ptr());
+    let mut cycle_im = _mm256_loadu_pd(lanes.cycle_im.as_ptr());
+    let mut cycle_length = _mm256_loadu_pd(lanes.cycle_length.as_ptr());
+    let mut steps = _mm256_loadu_pd(lanes.steps.as_ptr());
+
+    let one = _mm256_set1_pd(1.0);
+    let zero = _mm256_setzero_pd();
+    let escape = _mm256_set1_pd(limits.escape);
+    let period_tolerance = _mm256_set1_pd(limits.period_tolerance);
+    let max_iteration = _mm256_set1_pd(limits.max_iteration);
+
+    let (escaped, periodic, done) = loop {
+        let x2 = _mm256_mul_pd(zr, zr);
+        let y2 = _mm256_mul_pd(zi, zi);
+        let escaped = _mm256_and_pd(active, _mm256_cmp_pd(_mm256_add_pd(x2, y2), escape, _CMP_GE_OQ));
+        let live = _mm256_andnot_pd(escaped, active);
+
+        let xy = _mm256_mul_pd(zr, zi);
+        let yx = _mm256_mul_pd(zi, zr);
+        let nx = _mm256_add_pd(_mm256_sub_pd(x2, y2), cr);
+        let ny = _mm256_add_pd(_mm256_add_pd(xy, yx), ci);
+        zr = _mm256_blendv_pd(zr, nx, live);
+        zi = _mm256_blendv_pd(zi, ny, live);
+        n = _mm256_add_pd(n, _mm256_and_pd(live, one));
+
+        let dx = _mm256_sub_pd(zr, cycle_re);
+        let dy = _mm256_sub_pd(zi, cycle_im);
+        let d2 = _mm256_add_pd(_mm256_mul_pd(dx, dx), _mm256_mul_pd(dy, dy));
+        let periodic = _mm256_and_pd(live, _mm256_cmp_pd(d2, period_tolerance, _CMP_LT_OQ));
+
+        steps = _mm256_add_pd(steps, one);
+        let save = _mm256_cmp_pd(steps, cycle_length, _CMP_EQ_OQ);
+        cycle_re = _mm256_blendv_pd(cycle_re, zr, save);
+        cycle_im = _mm256_blendv_pd(cycle_im, zi, save);
+        cycle_length = _mm256_blendv_pd(cycle_length, _mm256_add_pd(cycle_length, cycle_length), save);
+        steps = _mm256_blendv_pd(steps, zero, save);
+
+        let limit = _mm256_cmp_pd(n, max_iteration, _CMP_EQ_OQ);
+        let done = _mm256_and_pd(active, _mm256_or_pd(_mm256_or_pd(escaped, periodic), limit));
+        if _mm256_movemask_pd(done) != 0 {
+            break (_mm256_movemask_pd(escaped), _mm256_movemask_pd(periodic), _mm256_movemask_pd(done));
+        }
+    };
+
+    _mm256_storeu_pd(lanes.zr.as_mut_ptr(), zr);
+    _mm256_storeu_pd(lanes.zi.as_mut_ptr(), zi);
+    _mm256_storeu_pd(lanes.n.as_mut_ptr(), n);
+    _mm256_storeu_pd(lanes.cycle_re.as_mut_ptr(), cycle_re);
+    _mm256_storeu_pd(lanes.cycle_im.as_mut_ptr(), cycle_im);
+    _mm256_storeu_pd(lanes.cycle_length.as_mut_ptr(), cycle_length);
+    _mm256_storeu_pd(lanes.steps.as_mut_ptr(), steps);
+    for l in 0..LANES {
+        lanes.escaped[l] = escaped & (1 << l) != 0;
+        lanes.periodic[l] = periodic & (1 << l) != 0;
+        lanes.done[l] = done & (1 << l) != 0;
+    }
+}
+
+//----------------------------------------------------------------------------
@@ -0,0 +1,192 @@
+//============================================================================
+//
+// A simple Mandelbrot image generator in Rust
+//
+// Rendering a frame in tiles on a pool of worker threads
+//
+// Copyright (c) 2014 Gavin Baker <gavinb@antonym.org>
+// Published under the MIT license
+//
+//============================================================================
+
+// The engine keeps a pool of worker threads for as long as it runs.  For
+// each render, each worker takes the next tile that nobody has started on,
+// works out every pixel in it and hands the results back to the engine
+// thread, which puts them in place.  Every pixel is computed on its own, so the frame
+// comes out the same however the tiles are shared out.  Between tiles the
+// engine checks for newer commands, and if the render has gone stale the
+// workers stop taking tiles.
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::vec::Vec;
+
+use job::{Job, Cancelled};
+use protocol::EngineStatus;
+
+// Width and height of a tile, in pixels
+static TILE_SIZE: u32 = 64;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+// Worker threads waiting for tasks
+pub struct Pool {
+    task_chan: Option<Sender<Task>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+//----------------------------------------------------------------------------
+
+// One worker per CPU
+pub fn default_threads() -> usize {
+    match thread::available_parallelism() {
+        Ok(n) => n.get(),
+        Err(_) => 1,
+    }
+}
+
+impl Pool {
+
+    pub fn new(threads: usize) -> Pool {
+        let (task_tx, task_rx) = channel::<Task>();
+        let task_rx = Arc::new(Mutex::new(task_rx));
+        let workers = (0..threads).map(|_| {
+            let task_rx = task_rx.clone();
+            thread::spawn(move || {
+                loop {
+                    let task = task_rx.lock().unwrap().recv();
+                    match task {
+                        Ok(task) => task(),
+                        Err(_) => break,
+                    }
+                }
+            })
+        }).collect();
+
+        Pool { task_chan: Some(task_tx), workers: workers }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    fn run(&self, task: Task) {
+        self.task_chan.as_ref().unwrap().send(task).unwrap();
+    }
+}
+
+// Let the workers finish their tasks and stop
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.task_chan = None;
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+//----------------------------------------------------------------------------
+
+// Pixel bounds x0, y0, x1, y1 of tile number `tile`, in rows of tiles
+fn tile_bounds(tile: usize, width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let across = width.div_ceil(TILE_SIZE);
+    let x0 = (tile as u32 % across) * TILE_SIZE;
+    let y0 = (tile as u32 / across) * TILE_SIZE;
+    let x1 = if x0 + TILE_SIZE < width { x0 + TILE_SIZE } else { width };
+    let y1 = if y0 + TILE_SIZE < height { y0 + TILE_SIZE } else { height };
+    (x0, y0, x1, y1)
+}
+
+// Evaluate `pixel` at every (x, y) of a width x height frame on the pool's
+// workers, reporting each finished tile, and return the results in row
+// order, unless the job is cancelled first
+pub fn render<T, F>(width: u32, height: u32, pool: &Pool, pixel: F,
+                    job: &Job) -> Result<Vec<T>, Cancelled>
+    where T: Send + 'static, F: Fn(u32, u32) -> T + Send + Sync + 'static {
+
+    render_spans(width, height, pool, move |y, x0, x1, values: &mut Vec<T>| {
+        for x in x0..x1 {
+            values.push(pixel(x, y));
+        }
+    }, job)
+}
+
+// As render, for a `span` that pushes the values of the pixels x0..x1 on
+// row y in one go
+pub fn render_spans<T, F>(width: u32, height: u32, pool: &Pool, span: F,
+                          job: &Job) -> Result<Vec<T>, Cancelled>
+    where T: Send + 'static, F: Fn(u32, u32, u32, &mut Vec<T>) + Send + Sync + 'static {
+
+    render_blocks(width, height, pool, move |x0, y0, x1, y1, values: &mut Vec<T>| {
+        for y in y0..y1 {
+            span(y, x0, x1, values);
+        }
+    }, job)
+}
+
+// As render, for a `block` that pushes the values of a whole tile from
+// (x0, y0) up to (x1, y1), in row order
+pub fn render_blocks<T, F>(width: u32, height: u32, pool: &Pool, block: F,
+                           job: &Job) -> Result<Vec<T>, Cancelled>
+    where T: Send + 'static, F: Fn(u32, u32, u32, u32, &mut Vec<T>) + Send + Sync + 'static {
+
+    let across = width.div_ceil(TILE_SIZE);
+    let down = height.div_ceil(TILE_SIZE);
+    let ntiles = (across * down) as usize;
+
+    let block = Arc::new(block);
+    let next = Arc::new(AtomicUsize::new(0));
+    let cancelled = job.cancel_flag();
+    let (tile_tx, tile_rx) = channel();
+
+    for _ in 0..pool.size() {
+        let block = block.clone();
+        let next = next.clone();
+        let cancelled = cancelled.clone();
+        let tile_tx = tile_tx.clone();
+        pool.run(Box::new(move || {
+            loop {
+                let tile = next.fetch_add(1, Ordering::SeqCst);
+                if tile >= ntiles || cancelled.load(Ordering::SeqCst) {
+                    break;
+                }
+                let (x0, y0, x1, y1) = tile_bounds(tile, width, height);
+                let mut values = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+                block(x0, y0, x1, y1, &mut values);
+                if tile_tx.send((tile, values)).is_err() {
+                    break;
+                }
+            }
+        }));
+    }
+    drop(tile_tx);
+
+    // Put each tile in place as it arrives, until every worker is done
+    let mut frame: Vec<Option<T>> = (0..width*height).map(|_| None).collect();
+    let mut done = 0;
+    let mut tiles = tile_rx.iter();
+    for (tile, values) in &mut tiles {
+        let (x0, y0, x1, _) = tile_bounds(tile, width, height);
+        let tile_width = (x1 - x0) as usize;
+        for (k, value) in values.into_iter().enumerate() {
+            let px = x0 as usize + k % tile_width;
+            let py = y0 as usize + k / tile_width;
+            frame[py * width as usize + px] = Some(value);
+        }
+        done += 1;
+        job.send(EngineStatus::TileComplete(job.generation(), done, ntiles as u32));
+        if job.check().is_err() {
+            break;
+        }
+    }
+
+    for _ in tiles {}
+
+    try!(job.check());
+    Ok(frame.into_iter().map(|value| value.unwrap()).collect())
+}
+
+//----------------------------------------------------------------------------